@@ -0,0 +1,13 @@
+#version 450
+
+layout (location = 0) in vec2 frag_uv;
+layout (location = 1) flat in uint frag_layer;
+
+layout (set = 0, binding = 1) uniform sampler2DArray block_textures;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+  color = texture(block_textures, vec3(frag_uv, frag_layer));
+}
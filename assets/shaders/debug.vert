@@ -0,0 +1,36 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+#include "frame.glsl"
+
+layout (location = 0) in vec4 position;
+
+layout (location = 0) out vec3 frag_world_position;
+layout (location = 1) flat out vec3 frag_chunk_color;
+
+out gl_PerVertex {
+  vec4 gl_Position;
+};
+
+// Scrambles the bits of `x` so neighbouring inputs come out far apart.
+uint hash(uint x)
+{
+  x ^= x >> 16;
+  x *= 0x7feb352du;
+  x ^= x >> 15;
+  x *= 0x846ca68bu;
+  x ^= x >> 16;
+  return x;
+}
+
+void main()
+{
+  vec4 world_position = draw.model * position;
+  gl_Position = frame.projection * frame.view * world_position;
+  frag_world_position = world_position.xyz;
+
+  // Every chunk is drawn with its own model matrix, so its translation tells them
+  // apart.
+  uvec3 offset = floatBitsToUint(draw.model[3].xyz);
+  uint id = hash(offset.x ^ hash(offset.y ^ hash(offset.z)));
+  frag_chunk_color = vec3(uvec3(id, id >> 8, id >> 16) & 0xffu) / 255.0;
+}
@@ -0,0 +1,10 @@
+#version 450
+
+layout (location = 1) flat in vec3 frag_chunk_color;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+  color = vec4(frag_chunk_color, 1.0);
+}
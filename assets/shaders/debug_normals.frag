@@ -0,0 +1,13 @@
+#version 450
+
+layout (location = 0) in vec3 frag_world_position;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+  // Meshes don't carry normals, but every face is flat, so the slope of the position
+  // across it gives one.
+  vec3 normal = normalize(cross(dFdx(frag_world_position), dFdy(frag_world_position)));
+  color = vec4(normal * 0.5 + 0.5, 1.0);
+}
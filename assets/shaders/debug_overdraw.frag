@@ -0,0 +1,10 @@
+#version 450
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+  // Added up over every fragment drawn to a pixel, so it gets brighter the more
+  // often it's drawn to. Ten layers and it's saturated red.
+  color = vec4(0.1, 0.05, 0.025, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 450
+
+layout (location = 0) in vec2 frag_uv;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+  // Red and green go from 0 to 1 across each repeat of the texture.
+  color = vec4(fract(frag_uv), 0.0, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+#include "frame.glsl"
+
+layout (location = 0) in vec4 position;
+layout (location = 1) in vec2 uv;
+
+layout (location = 0) out vec2 frag_uv;
+
+out gl_PerVertex {
+  vec4 gl_Position;
+};
+
+void main()
+{
+  gl_Position = frame.projection * frame.view * draw.model * position;
+  frag_uv = uv;
+}
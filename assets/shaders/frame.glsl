@@ -0,0 +1,11 @@
+// Set 0, binding 0: rewritten by the renderer at the start of every frame.
+layout (set = 0, binding = 0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  float time;
+} frame;
+
+// Set per draw, see `DrawConstants`.
+layout (push_constant) uniform DrawConstants {
+  mat4 model;
+} draw;
@@ -0,0 +1,9 @@
+#version 450
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+  // Shade by depth so overlapping geometry can be told apart, white at z = 0.
+  color = vec4(vec3(1.0 - gl_FragCoord.z), 1.0);
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout (push_constant) uniform Tint {
+  vec4 offset;
+  vec4 color;
+} tint;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+  color = tint.color;
+}
@@ -0,0 +1,19 @@
+#version 450
+
+// Set per draw, see `Tint` in golden.rs.
+layout (push_constant) uniform Tint {
+  vec4 offset;
+  vec4 color;
+} tint;
+
+layout (location = 0) in vec4 position;
+
+out gl_PerVertex {
+  vec4 gl_Position;
+};
+
+void main()
+{
+  // Straight into clip space, without the camera.
+  gl_Position = position + tint.offset;
+}
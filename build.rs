@@ -0,0 +1,205 @@
+//! Embeds the SPIR-V for the GLSL in `assets/shaders` with `include_bytes!`, so builds
+//! don't need shaderc at all. The SPIR-V is committed in `assets/spirv`; the
+//! `build-shaders` feature compiles it here again with shaderc and writes it back there,
+//! for after a shader changes. With the `runtime-shaders` feature the shaders are
+//! compiled when they're loaded instead, and nothing is embedded.
+//!
+//! Next to each `.spv` is a `.hash` of the source it was compiled from, includes and
+//! all, and the build fails if the source has changed since, rather than embedding
+//! stale SPIR-V.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+const SHADER_DIR: &str = "assets/shaders";
+const SPIRV_DIR: &str = "assets/spirv";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+    println!("cargo:rerun-if-changed={}", SPIRV_DIR);
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut embedded = String::from("pub const EMBEDDED: &[(&str, &[u8])] = &[\n");
+
+    if env::var_os("CARGO_FEATURE_RUNTIME_SHADERS").is_none() {
+        #[cfg(feature = "build-shaders")]
+        let mut compiler = compile::Compiler::new();
+        let mut files = Vec::new();
+        collect_files(Path::new(SHADER_DIR), &mut files);
+        files.sort();
+        for path in files {
+            println!("cargo:rerun-if-changed={}", path.display());
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("vert") | Some("frag") => {}
+                // Anything else is only there to be included.
+                _ => continue,
+            }
+            let name = path
+                .strip_prefix(SHADER_DIR)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            let spv_path = Path::new(SPIRV_DIR).join(format!("{}.spv", name.replace('/', "_")));
+            let hash_path = spv_path.with_extension("hash");
+            let hash = source_hash(&path);
+            #[cfg(feature = "build-shaders")]
+            {
+                // Only written when they changed, since that reruns this script.
+                let spirv = compiler.compile(&path);
+                if fs::read(&spv_path).ok().as_ref() != Some(&spirv) {
+                    fs::write(&spv_path, spirv).unwrap();
+                }
+                if fs::read_to_string(&hash_path).ok().as_deref() != Some(&hash) {
+                    fs::write(&hash_path, &hash).unwrap();
+                }
+            }
+            if !spv_path.is_file() {
+                panic!(
+                    "There's no SPIR-V for {} in {}. Build once with --features build-shaders \
+                     to compile it, and commit the result.",
+                    path.display(),
+                    SPIRV_DIR
+                );
+            }
+            if fs::read_to_string(&hash_path)
+                .ok()
+                .as_deref()
+                .map(str::trim)
+                != Some(&hash)
+            {
+                panic!(
+                    "{} changed since {} was compiled from it. Build once with --features \
+                     build-shaders to compile it again, and commit the result.",
+                    path.display(),
+                    spv_path.display()
+                );
+            }
+            let spv_path = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join(spv_path);
+            embedded.push_str(&format!(
+                "    ({:?}, include_bytes!({:?})),\n",
+                name, spv_path
+            ));
+        }
+    }
+
+    embedded.push_str("];\n");
+    fs::write(out_dir.join("shaders.rs"), embedded).unwrap();
+}
+
+/// Every file under `dir`, includes too, so editing any of them triggers a rebuild.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// A hash of the shader at `path` and everything it includes, as hex. Line endings
+/// are left out, so checkouts that convert them don't count as changes.
+fn source_hash(path: &Path) -> String {
+    let mut hash = Fnv(0xcbf2_9ce4_8422_2325);
+    hash_source(path, &mut hash, &mut Vec::new());
+    format!("{:016x}", hash.0)
+}
+
+/// Hashes `path`, then every file it includes that hasn't been already, looked up the
+/// same way `renderer::shaders` does.
+fn hash_source(path: &Path, hash: &mut Fnv, seen: &mut Vec<PathBuf>) {
+    if seen.iter().any(|seen| seen == path) {
+        return;
+    }
+    seen.push(path.to_path_buf());
+    let source = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Couldn't read {}: {}", path.display(), e))
+        .replace('\r', "");
+    hash.write(source.as_bytes());
+    for line in source.lines() {
+        let requested = match line.trim().strip_prefix("#include") {
+            Some(requested) => requested.trim(),
+            None => continue,
+        };
+        let included = if requested.starts_with('"') {
+            path.parent()
+                .unwrap_or_else(|| Path::new(SHADER_DIR))
+                .join(requested.trim_matches('"'))
+        } else if requested.starts_with('<') {
+            Path::new(SHADER_DIR).join(requested.trim_start_matches('<').trim_end_matches('>'))
+        } else {
+            continue;
+        };
+        hash_source(&included, hash, seen);
+    }
+}
+
+/// 64 bit FNV-1a, which unlike `DefaultHasher` is the same on every toolchain.
+struct Fnv(u64);
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(feature = "build-shaders")]
+mod compile {
+    use super::SHADER_DIR;
+
+    use shaderc_build as shaderc;
+
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    pub struct Compiler(shaderc::Compiler);
+
+    impl Compiler {
+        pub fn new() -> Self {
+            Compiler(shaderc::Compiler::new().expect("shaderc not found!"))
+        }
+
+        /// Compiles one shader, resolving `#include`s the way `renderer::shaders` does,
+        /// and fails the build with shaderc's diagnostics if it doesn't compile.
+        pub fn compile(&mut self, path: &Path) -> Vec<u8> {
+            let kind = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("vert") => shaderc::ShaderKind::Vertex,
+                _ => shaderc::ShaderKind::Fragment,
+            };
+            let source = fs::read_to_string(path).unwrap();
+            let mut options = shaderc::CompileOptions::new().unwrap();
+            options.set_include_callback(|requested, include_type, requesting, _depth| {
+                let base = match include_type {
+                    shaderc::IncludeType::Relative => Path::new(requesting)
+                        .parent()
+                        .unwrap_or_else(|| Path::new(SHADER_DIR))
+                        .to_path_buf(),
+                    shaderc::IncludeType::Standard => PathBuf::from(SHADER_DIR),
+                };
+                let path = base.join(requested);
+                let content = fs::read_to_string(&path)
+                    .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+                Ok(shaderc::ResolvedInclude {
+                    resolved_name: path.display().to_string(),
+                    content,
+                })
+            });
+            match self.0.compile_into_spirv(
+                &source,
+                kind,
+                &path.display().to_string(),
+                "main",
+                Some(&options),
+            ) {
+                Ok(artifact) => artifact.as_binary_u8().to_vec(),
+                Err(e) => panic!("Couldn't compile {}:\n{}", path.display(), e),
+            }
+        }
+    }
+}
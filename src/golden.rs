@@ -0,0 +1,602 @@
+//! Golden image checks. Known scenes are rendered headless through `HalState` and
+//! compared against reference PNGs, so changes to pipelines, shaders or blending
+//! can't silently change what ends up on screen.
+//!
+//! `cargo test --features <backend>` checks every scene, skipping them on machines
+//! without an adapter, as does `cargo run --features <backend> -- golden`.
+//! `cargo run --features <backend> -- golden --bless` (re)writes the references.
+
+use crate::renderer::{
+    perspective, BlendMode, BlockVertex, Camera, DrawConstants, HalState, PipelineBuilder,
+    PushConstants, RendererConfig, RendererError, RgbaImage, ShaderSet, Triangle, PLAIN_SHADERS,
+};
+
+use gfx_hal::{
+    pso::{DepthTest, Face, PolygonMode, Rect, ShaderStageFlags},
+    Primitive,
+};
+
+use std::{fmt, fs, path::Path};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// Where the reference images live, relative to the crate root.
+pub const GOLDEN_DIR: &str = "tests/golden";
+
+/// Largest per channel difference still counted as a match, since drivers don't
+/// all rasterize and blend exactly alike.
+pub const DEFAULT_TOLERANCE: u8 = 2;
+
+/// Draws straight in clip space, moved and colored by a `Tint`.
+const TINTED_SHADERS: ShaderSet = ShaderSet {
+    vertex: "tinted.vert",
+    fragment: "tinted.frag",
+};
+
+/// The push constants `TINTED_SHADERS` take, in place of `DrawConstants`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Tint {
+    offset: [f32; 4],
+    color: [f32; 4],
+}
+
+unsafe impl PushConstants for Tint {
+    const STAGES: ShaderStageFlags = ShaderStageFlags::from_bits_truncate(
+        ShaderStageFlags::VERTEX.bits() | ShaderStageFlags::FRAGMENT.bits(),
+    );
+}
+
+pub struct Scene {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub draw: fn(&mut HalState) -> Result<(), RendererError>,
+}
+
+/// Every scene the harness knows about.
+pub fn scenes() -> Vec<Scene> {
+    vec![
+        Scene {
+            name: "clear",
+            width: 64,
+            height: 64,
+            draw: |hal| hal.draw_clear_frame([0.1, 0.2, 0.3, 1.0]),
+        },
+        Scene {
+            name: "triangle",
+            width: 400,
+            height: 300,
+            // What `render` draws with the cursor in the middle of the window.
+            draw: |hal| {
+                hal.draw_triangle_frame(Triangle {
+                    points: [[-0.5, 0.5], [-0.5, -0.5], [0.5, 0.5]],
+                })
+            },
+        },
+        Scene {
+            name: "split",
+            width: 400,
+            height: 300,
+            // Two views side by side, plus one hanging off the bottom right corner to
+            // check clipping.
+            draw: |hal| {
+                let triangle = Triangle {
+                    points: [[-0.5, 0.5], [0.0, -0.5], [0.5, 0.5]],
+                };
+                hal.draw_triangles_frame(&[
+                    (
+                        Rect {
+                            x: 0,
+                            y: 0,
+                            w: 200,
+                            h: 300,
+                        },
+                        triangle,
+                    ),
+                    (
+                        Rect {
+                            x: 200,
+                            y: 0,
+                            w: 200,
+                            h: 300,
+                        },
+                        triangle,
+                    ),
+                    (
+                        Rect {
+                            x: 300,
+                            y: 225,
+                            w: 200,
+                            h: 150,
+                        },
+                        triangle,
+                    ),
+                ])
+            },
+        },
+        Scene {
+            name: "meshes",
+            width: 256,
+            height: 256,
+            // A far quad drawn after a near one, so it only shows where they don't
+            // overlap if the depth test works.
+            draw: |hal| {
+                let indices: [u16; 6] = [0, 1, 2, 2, 1, 3];
+                let quad = |x: f32, y: f32, z: f32| {
+                    [
+                        [x - 0.5, y - 0.5, z],
+                        [x + 0.5, y - 0.5, z],
+                        [x - 0.5, y + 0.5, z],
+                        [x + 0.5, y + 0.5, z],
+                    ]
+                };
+                let near = hal.create_mesh(&quad(-0.2, -0.2, 0.25), &indices)?;
+                let far = hal.create_mesh(&quad(0.2, 0.2, 0.75), &indices)?;
+                hal.draw_meshes_frame(
+                    [0.1, 0.2, 0.3, 1.0],
+                    &[
+                        (near, DrawConstants::default()),
+                        (far, DrawConstants::default()),
+                    ],
+                )
+            },
+        },
+        Scene {
+            name: "camera",
+            width: 320,
+            height: 240,
+            // A unit quad lying on the ground, seen from above and to the side.
+            draw: |hal| {
+                let ground = hal.create_mesh(
+                    &[
+                        [-1.0f32, 0.0, -1.0],
+                        [1.0, 0.0, -1.0],
+                        [-1.0, 0.0, 1.0],
+                        [1.0, 0.0, 1.0],
+                    ],
+                    &[0u16, 1, 2, 2, 1, 3],
+                )?;
+                hal.set_camera(Camera::look_at(
+                    [2.0, 2.0, 3.0],
+                    [0.0, 0.0, 0.0],
+                    [0.0, 1.0, 0.0],
+                    perspective(std::f32::consts::FRAC_PI_3, 320.0 / 240.0, 0.1, 100.0),
+                ));
+                hal.draw_meshes_frame([0.1, 0.2, 0.3, 1.0], &[(ground, DrawConstants::default())])
+            },
+        },
+        Scene {
+            name: "push_constants",
+            width: 256,
+            height: 256,
+            // One small quad drawn in each corner, moved there by its draw constants.
+            draw: |hal| {
+                let quad = hal.create_mesh(
+                    &[
+                        [-0.25f32, -0.25, 0.5],
+                        [0.25, -0.25, 0.5],
+                        [-0.25, 0.25, 0.5],
+                        [0.25, 0.25, 0.5],
+                    ],
+                    &[0u16, 1, 2, 2, 1, 3],
+                )?;
+                let draws: Vec<_> = [[-0.5, -0.5], [0.5, -0.5], [-0.5, 0.5], [0.5, 0.5]]
+                    .iter()
+                    .map(|&[x, y]| (quad, DrawConstants::translation([x, y, 0.0])))
+                    .collect();
+                hal.draw_meshes_frame([0.1, 0.2, 0.3, 1.0], &draws)
+            },
+        },
+        Scene {
+            name: "push_constant_types",
+            width: 256,
+            height: 256,
+            // The same quad twice, with a pipeline that takes a `Tint` for each draw
+            // rather than `DrawConstants`: orange on the left, blue on the right.
+            draw: |hal| {
+                let tinted = hal.create_pipeline(
+                    "tinted",
+                    PipelineBuilder::new(TINTED_SHADERS)
+                        .push_constants::<Tint>()
+                        .build(),
+                )?;
+                let quad = hal.create_mesh_with_pipeline(
+                    tinted,
+                    &[
+                        [-0.25f32, -0.25, 0.5],
+                        [0.25, -0.25, 0.5],
+                        [-0.25, 0.25, 0.5],
+                        [0.25, 0.25, 0.5],
+                    ],
+                    &[0u16, 1, 2, 2, 1, 3],
+                )?;
+                let tint = |x: f32, color: [f32; 4]| Tint {
+                    offset: [x, 0.0, 0.0, 0.0],
+                    color,
+                };
+                hal.draw_meshes_frame(
+                    [0.1, 0.2, 0.3, 1.0],
+                    &[
+                        (quad, tint(-0.5, [1.0, 0.5, 0.0, 1.0])),
+                        (quad, tint(0.5, [0.0, 0.5, 1.0, 1.0])),
+                    ],
+                )
+            },
+        },
+        Scene {
+            name: "textures",
+            width: 256,
+            height: 256,
+            // Two quads side by side, textured from different layers of the block
+            // textures: a checkerboard and a gradient.
+            draw: |hal| {
+                let checkers = RgbaImage::new(
+                    8,
+                    8,
+                    (0..64)
+                        .flat_map(|i| {
+                            let on = (i % 8 + i / 8) % 2 == 0;
+                            if on {
+                                vec![255, 255, 255, 255]
+                            } else {
+                                vec![200, 40, 40, 255]
+                            }
+                        })
+                        .collect(),
+                );
+                let gradient = RgbaImage::new(
+                    8,
+                    8,
+                    (0..64u8)
+                        .flat_map(|i| vec![0, (i % 8) * 32, (i / 8) * 32, 255])
+                        .collect(),
+                );
+                hal.set_block_textures(&[checkers, gradient])?;
+                let quad = |x: f32, layer: u32| {
+                    let corner = |dx: f32, dy: f32, u: f32, v: f32| BlockVertex {
+                        position: [x + dx, dy, 0.5],
+                        uv: [u, v],
+                        layer,
+                    };
+                    [
+                        corner(-0.4, -0.4, 0.0, 0.0),
+                        corner(0.4, -0.4, 1.0, 0.0),
+                        corner(-0.4, 0.4, 0.0, 1.0),
+                        corner(0.4, 0.4, 1.0, 1.0),
+                    ]
+                };
+                let indices: [u16; 6] = [0, 1, 2, 2, 1, 3];
+                let left = hal.create_mesh(&quad(-0.5, 0), &indices)?;
+                let right = hal.create_mesh(&quad(0.5, 1), &indices)?;
+                hal.draw_meshes_frame(
+                    [0.1, 0.2, 0.3, 1.0],
+                    &[
+                        (left, DrawConstants::default()),
+                        (right, DrawConstants::default()),
+                    ],
+                )
+            },
+        },
+        Scene {
+            name: "pipelines",
+            width: 256,
+            height: 256,
+            // Top left, two triangles wound opposite ways with back faces culled, so
+            // only one shows. Top right, a line strip around a square. Across the
+            // bottom, a near quad with a far one drawn over it anyway, blended and
+            // without the depth test.
+            draw: |hal| {
+                let culled = hal.create_pipeline(
+                    "culled",
+                    PipelineBuilder::new(PLAIN_SHADERS)
+                        .cull_face(Face::BACK)
+                        .build(),
+                )?;
+                hal.create_pipeline(
+                    "lines",
+                    PipelineBuilder::new(PLAIN_SHADERS)
+                        .primitive(Primitive::LineStrip)
+                        .build(),
+                )?;
+                hal.create_pipeline(
+                    "overlay",
+                    PipelineBuilder::new(PLAIN_SHADERS)
+                        .vertex::<[f32; 3]>()
+                        .depth_test(DepthTest::Off)
+                        .blend(BlendMode::Alpha)
+                        .build(),
+                )?;
+                let lines = hal.pipeline("lines").unwrap();
+                let overlay = hal.pipeline("overlay").unwrap();
+
+                let triangles = hal.create_mesh_with_pipeline(
+                    culled,
+                    &[
+                        [-0.9f32, -0.9, 0.5],
+                        [-0.6, -0.9, 0.5],
+                        [-0.9, -0.6, 0.5],
+                        [-0.5, -0.5, 0.5],
+                        [-0.2, -0.5, 0.5],
+                        [-0.5, -0.2, 0.5],
+                    ],
+                    &[0u16, 1, 2, 3, 5, 4],
+                )?;
+                let square = hal.create_mesh_with_pipeline(
+                    lines,
+                    &[
+                        [0.2f32, -0.8, 0.5],
+                        [0.8, -0.8, 0.5],
+                        [0.8, -0.2, 0.5],
+                        [0.2, -0.2, 0.5],
+                    ],
+                    &[0u16, 1, 2, 3, 0],
+                )?;
+                let quad = |x: f32, z: f32| {
+                    [
+                        [x - 0.4, 0.1, z],
+                        [x + 0.4, 0.1, z],
+                        [x - 0.4, 0.9, z],
+                        [x + 0.4, 0.9, z],
+                    ]
+                };
+                let indices: [u16; 6] = [0, 1, 2, 2, 1, 3];
+                let near = hal.create_mesh(&quad(-0.2, 0.25), &indices)?;
+                let behind = hal.create_mesh_with_pipeline(overlay, &quad(0.2, 0.75), &indices)?;
+                hal.draw_meshes_frame(
+                    [0.1, 0.2, 0.3, 1.0],
+                    &[
+                        (triangles, DrawConstants::default()),
+                        (square, DrawConstants::default()),
+                        (near, DrawConstants::default()),
+                        (behind, DrawConstants::default()),
+                    ],
+                )
+            },
+        },
+        Scene {
+            name: "polygon_mode",
+            width: 256,
+            height: 256,
+            // A quad drawn as lines, so both triangles' edges show, diagonal included.
+            draw: |hal| {
+                let outlined = hal.create_pipeline(
+                    "outlined",
+                    PipelineBuilder::new(PLAIN_SHADERS)
+                        .polygon_mode(PolygonMode::Line(1.0))
+                        .build(),
+                )?;
+                let quad = hal.create_mesh_with_pipeline(
+                    outlined,
+                    &[
+                        [-0.5f32, -0.5, 0.5],
+                        [0.5, -0.5, 0.5],
+                        [-0.5, 0.5, 0.5],
+                        [0.5, 0.5, 0.5],
+                    ],
+                    &[0u16, 1, 2, 2, 1, 3],
+                )?;
+                hal.draw_meshes_frame([0.1, 0.2, 0.3, 1.0], &[(quad, DrawConstants::default())])
+            },
+        },
+    ]
+}
+
+#[derive(Debug)]
+pub struct Comparison {
+    /// Number of pixels with a channel further than the tolerance from the reference.
+    pub mismatched: usize,
+    pub max_delta: u8,
+    /// Mismatched pixels in red over a dimmed copy of the reference.
+    pub diff: RgbaImage,
+}
+
+/// Compares two images channel by channel. Returns `None` if they aren't the same size.
+pub fn compare(expected: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> Option<Comparison> {
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        return None;
+    }
+    let mut mismatched = 0;
+    let mut max_delta = 0;
+    let mut diff = Vec::with_capacity(expected.pixels.len());
+    for (e, a) in expected.pixels.chunks(4).zip(actual.pixels.chunks(4)) {
+        let delta = e
+            .iter()
+            .zip(a)
+            .map(|(&e, &a)| (i16::from(e) - i16::from(a)).unsigned_abs() as u8)
+            .max()
+            .unwrap_or(0);
+        max_delta = max_delta.max(delta);
+        if delta > tolerance {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let luma = ((u16::from(e[0]) + u16::from(e[1]) + u16::from(e[2])) / 12) as u8;
+            diff.extend_from_slice(&[luma, luma, luma, 255]);
+        }
+    }
+    Some(Comparison {
+        mismatched,
+        max_delta,
+        diff: RgbaImage::new(expected.width, expected.height, diff),
+    })
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Passed,
+    Blessed,
+    MissingReference,
+    SizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    Failed {
+        mismatched: usize,
+        max_delta: u8,
+    },
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Passed | Outcome::Blessed)
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Blessed => write!(f, "reference written"),
+            Outcome::MissingReference => write!(f, "no reference image, run with --bless"),
+            Outcome::SizeMismatch { expected, actual } => write!(
+                f,
+                "expected {}x{} but rendered {}x{}",
+                expected.0, expected.1, actual.0, actual.1
+            ),
+            Outcome::Failed {
+                mismatched,
+                max_delta,
+            } => write!(f, "{} pixels differ, by up to {}", mismatched, max_delta),
+        }
+    }
+}
+
+/// Renders one scene and checks it against `<dir>/<name>.png`. On failure the
+/// rendered frame is written next to it as `<name>.actual.png`, plus a
+/// `<name>.diff.png` if the sizes matched.
+pub fn run_scene(
+    scene: &Scene,
+    dir: &Path,
+    tolerance: u8,
+    bless: bool,
+    config: &RendererConfig,
+) -> Result<Outcome, RendererError> {
+    let mut hal = HalState::new_headless("Golden", scene.width, scene.height, config.clone())?;
+    (scene.draw)(&mut hal)?;
+    let actual = hal.read_pixels()?;
+
+    // Whatever the outcome something gets written there, and a fresh checkout may not
+    // have it yet.
+    fs::create_dir_all(dir)?;
+    let reference = dir.join(format!("{}.png", scene.name));
+    if bless {
+        actual.save_png(&reference)?;
+        return Ok(Outcome::Blessed);
+    }
+    if !reference.exists() {
+        actual.save_png(dir.join(format!("{}.actual.png", scene.name)))?;
+        return Ok(Outcome::MissingReference);
+    }
+
+    let expected = RgbaImage::load_png(&reference)?;
+    let comparison = match compare(&expected, &actual, tolerance) {
+        Some(comparison) => comparison,
+        None => {
+            actual.save_png(dir.join(format!("{}.actual.png", scene.name)))?;
+            return Ok(Outcome::SizeMismatch {
+                expected: (expected.width, expected.height),
+                actual: (actual.width, actual.height),
+            });
+        }
+    };
+    if comparison.mismatched == 0 {
+        return Ok(Outcome::Passed);
+    }
+    actual.save_png(dir.join(format!("{}.actual.png", scene.name)))?;
+    comparison
+        .diff
+        .save_png(dir.join(format!("{}.diff.png", scene.name)))?;
+    Ok(Outcome::Failed {
+        mismatched: comparison.mismatched,
+        max_delta: comparison.max_delta,
+    })
+}
+
+/// Runs every scene on a renderer set up with `config`, logging the result of each.
+/// Returns whether all of them passed.
+pub fn run(
+    dir: &Path,
+    tolerance: u8,
+    bless: bool,
+    config: &RendererConfig,
+) -> Result<bool, RendererError> {
+    let mut all_passed = true;
+    for scene in scenes() {
+        let outcome = run_scene(&scene, dir, tolerance, bless, config)?;
+        if outcome.passed() {
+            info!("{}: {}", scene.name, outcome);
+        } else {
+            error!("{}: {}", scene.name, outcome);
+            all_passed = false;
+        }
+    }
+    Ok(all_passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, pixel: [u8; 4]) -> RgbaImage {
+        let pixels = (0..width * height).flat_map(|_| pixel.to_vec()).collect();
+        RgbaImage::new(width, height, pixels)
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let expected = image(4, 3, [10, 20, 30, 255]);
+        let comparison = compare(&expected, &expected.clone(), 0).unwrap();
+        assert_eq!(comparison.mismatched, 0);
+        assert_eq!(comparison.max_delta, 0);
+        assert_eq!((comparison.diff.width, comparison.diff.height), (4, 3));
+    }
+
+    #[test]
+    fn differences_within_the_tolerance_match() {
+        let expected = image(2, 2, [100, 100, 100, 255]);
+        let actual = image(2, 2, [102, 98, 100, 255]);
+        let comparison = compare(&expected, &actual, 2).unwrap();
+        assert_eq!(comparison.mismatched, 0);
+        assert_eq!(comparison.max_delta, 2);
+    }
+
+    #[test]
+    fn differences_past_the_tolerance_are_marked() {
+        let expected = image(2, 1, [100, 100, 100, 255]);
+        let mut actual = expected.clone();
+        actual.pixels[4..8].copy_from_slice(&[100, 100, 103, 255]);
+        let comparison = compare(&expected, &actual, 2).unwrap();
+        assert_eq!(comparison.mismatched, 1);
+        assert_eq!(comparison.max_delta, 3);
+        assert_eq!(&comparison.diff.pixels[4..8], &[255, 0, 0, 255]);
+        assert_ne!(&comparison.diff.pixels[0..4], &[255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn different_sizes_dont_compare() {
+        let expected = image(4, 4, [0, 0, 0, 255]);
+        assert!(compare(&expected, &image(4, 3, [0, 0, 0, 255]), 255).is_none());
+        assert!(compare(&expected, &image(3, 4, [0, 0, 0, 255]), 255).is_none());
+    }
+
+    #[test]
+    fn scenes_match_references() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN_DIR);
+        let config = RendererConfig::from_env();
+        let mut failures = Vec::new();
+        for scene in scenes() {
+            match run_scene(&scene, &dir, DEFAULT_TOLERANCE, false, &config) {
+                Ok(outcome) if outcome.passed() => {}
+                Ok(outcome) => failures.push(format!("{}: {}", scene.name, outcome)),
+                Err(RendererError::NoAdapter(reason)) => {
+                    eprintln!("Skipping the golden images: {}", reason);
+                    return;
+                }
+                Err(e) => failures.push(format!("{}: {}", scene.name, e)),
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
@@ -1,6 +1,9 @@
+mod golden;
 mod renderer;
 
 use renderer::*;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 use winit::dpi::*;
 use winit::*;
 
@@ -10,8 +13,34 @@ use log::{debug, error, info, trace, warn};
 fn main() {
     simple_logger::init().unwrap();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut config = RendererConfig::from_env();
+    if let Some(choice) = flag_value(&args, "--adapter") {
+        config.adapter = Some(AdapterChoice::parse(choice));
+    }
+    if args.first().map(String::as_str) == Some("golden") {
+        let bless = args.iter().any(|arg| arg == "--bless");
+        let dir = Path::new(golden::GOLDEN_DIR);
+        std::process::exit(
+            match golden::run(dir, golden::DEFAULT_TOLERANCE, bless, &config) {
+                Ok(true) => 0,
+                Ok(false) => 1,
+                Err(e) => {
+                    error!("Golden run failed: {}", e);
+                    2
+                }
+            },
+        );
+    }
+
     let mut winit_state = WinitState::default();
-    let mut hal_state = HalState::new(&winit_state.window, "New Window").unwrap();
+    let mut hal_state = match HalState::new(&winit_state.window, "New Window", config) {
+        Ok(hal_state) => hal_state,
+        Err(e) => {
+            error!("Couldn't set up the renderer: {}", e);
+            std::process::exit(1);
+        }
+    };
     let mut local_state = LocalState {
         frame_width: winit_state.size.width,
         frame_height: winit_state.size.height,
@@ -27,19 +56,142 @@ fn main() {
 
         local_state.update_from_input(&inputs);
 
-        if let Some((x, y)) = inputs.new_frame_size {
-            hal_state.recreate_swapchain(&winit_state.window);
+        if inputs.new_frame_size.is_some() {
+            if let Err(e) = hal_state.recreate_swapchain(&winit_state.window) {
+                error!("Couldn't recreate the swapchain: {}", e);
+                if !e.is_recoverable() {
+                    break;
+                }
+            }
             continue;
         }
 
+        if inputs.screenshot_requested {
+            let path = Path::new(CAPTURE_DIR).join(format!("screenshot-{}.png", timestamp()));
+            let requested = std::fs::create_dir_all(CAPTURE_DIR)
+                .map_err(RendererError::from)
+                .and_then(|_| hal_state.capture_next_frame(path));
+            if let Err(e) = requested {
+                error!("Couldn't take a screenshot: {}", e);
+            }
+        }
+        if inputs.capture_sequence_toggled {
+            if let Some(frames) = hal_state.stop_capture_sequence() {
+                info!("Stopped capturing after {} frames", frames);
+            } else {
+                let dir = Path::new(CAPTURE_DIR).join(format!("sequence-{}", timestamp()));
+                if let Err(e) = hal_state.start_capture_sequence(dir) {
+                    error!("Couldn't start capturing frames: {}", e);
+                }
+            }
+        }
+
+        if inputs.vsync_toggled || inputs.uncapped_toggled || inputs.triple_buffering_toggled {
+            if let Some(mut present) = hal_state.present_config() {
+                present.vsync ^= inputs.vsync_toggled;
+                present.uncapped ^= inputs.uncapped_toggled;
+                present.triple_buffering ^= inputs.triple_buffering_toggled;
+                match hal_state.set_present_config(present) {
+                    Ok(Some((mode, images))) => info!(
+                        "Presenting with {:?} and {} images for {:?}",
+                        mode, images, present
+                    ),
+                    Ok(None) => info!("{:?} applies once the window is restored", present),
+                    Err(e) => {
+                        error!("Couldn't change how frames are presented: {}", e);
+                        if !e.is_recoverable() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if inputs.msaa_cycled {
+            match cycle_msaa(&mut hal_state) {
+                Ok(samples) => info!("MSAA is now {}x", samples),
+                Err(e) => {
+                    error!("Couldn't change MSAA: {}", e);
+                    if !e.is_recoverable() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Pipelines are only replaced if every one of them builds, so a mode the device
+        // or the shaders can't do leaves everything as it was and is only logged.
+        if inputs.wireframe_toggled {
+            let wireframe = !hal_state.wireframe();
+            match hal_state.set_wireframe(wireframe) {
+                Ok(()) => info!("Wireframe is now {}", if wireframe { "on" } else { "off" }),
+                Err(e) => error!("Couldn't toggle wireframe: {}", e),
+            }
+        }
+
+        if inputs.debug_view_cycled {
+            let current = hal_state.debug_view();
+            let mut view = current.next();
+            // One that can't be drawn is skipped, rather than tried again on every press.
+            while view != current {
+                match hal_state.set_debug_view(view) {
+                    Ok(()) => {
+                        info!("Debug view is now {}", view);
+                        break;
+                    }
+                    Err(e) => {
+                        error!("Couldn't switch to the {} debug view: {}", view, e);
+                        view = view.next();
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "runtime-shaders")]
+        if let Err(e) = hal_state.reload_changed_shaders() {
+            error!("Couldn't reload shaders: {}", e);
+            if !e.is_recoverable() {
+                break;
+            }
+        }
+
         if let Err(e) = render(&mut hal_state, &local_state) {
-            error!("Rendering Error: {:?}", e);
-            continue;
+            error!("Rendering Error: {}", e);
+            if !e.is_recoverable() {
+                break;
+            }
         }
     }
+    info!("{}", hal_state.memory_stats());
+}
+
+/// Where F2 screenshots and F3 frame sequences are written.
+pub const CAPTURE_DIR: &str = "captures";
+
+/// Doubles the samples per pixel, going back to 1 past what the device can do.
+fn cycle_msaa(hal: &mut HalState) -> Result<u8, RendererError> {
+    let next = hal.msaa() * 2;
+    if next <= 8 && hal.set_msaa(next)? == next {
+        return Ok(next);
+    }
+    hal.set_msaa(1)
+}
+
+/// What follows `flag` on the command line, as in `--adapter 1`.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let position = args.iter().position(|arg| arg == flag)?;
+    args.get(position + 1).map(String::as_str)
 }
 
-pub fn render(hal: &mut HalState, local: &LocalState) -> Result<(), &'static str> {
+/// Seconds since the epoch, to keep capture names from colliding.
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn render(hal: &mut HalState, local: &LocalState) -> Result<(), RendererError> {
     hal.draw_triangle_frame(Triangle {
         points: [
             [-0.5, 0.5],
@@ -105,6 +257,14 @@ pub struct UserInput {
     pub end_requested: bool,
     pub new_frame_size: Option<(f64, f64)>,
     pub new_mouse_position: Option<(f64, f64)>,
+    pub screenshot_requested: bool,
+    pub capture_sequence_toggled: bool,
+    pub vsync_toggled: bool,
+    pub uncapped_toggled: bool,
+    pub triple_buffering_toggled: bool,
+    pub msaa_cycled: bool,
+    pub wireframe_toggled: bool,
+    pub debug_view_cycled: bool,
 }
 impl UserInput {
     pub fn poll_events_loop(events_loop: &mut EventsLoop) -> Self {
@@ -126,6 +286,29 @@ impl UserInput {
             } => {
                 output.new_mouse_position = Some((position.x, position.y));
             }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => match key {
+                VirtualKeyCode::F2 => output.screenshot_requested = true,
+                VirtualKeyCode::F3 => output.capture_sequence_toggled ^= true,
+                VirtualKeyCode::F4 => output.vsync_toggled ^= true,
+                VirtualKeyCode::F5 => output.uncapped_toggled ^= true,
+                VirtualKeyCode::F6 => output.triple_buffering_toggled ^= true,
+                VirtualKeyCode::F7 => output.msaa_cycled = true,
+                VirtualKeyCode::F8 => output.wireframe_toggled ^= true,
+                VirtualKeyCode::F9 => output.debug_view_cycled = true,
+                _ => (),
+            },
             _ => (),
         });
         output
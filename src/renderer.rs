@@ -1,336 +1,852 @@
-#![allow(clippy::len_zero)]
+// The empty backend makes most of its objects `()`.
+#![cfg_attr(
+    all(
+        feature = "empty",
+        not(any(feature = "dx12", feature = "metal", feature = "vulkan"))
+    ),
+    allow(clippy::let_unit_value, clippy::unit_arg)
+)]
 
 #[cfg(feature = "dx12")]
 use gfx_backend_dx12 as back;
+#[cfg(all(
+    feature = "empty",
+    not(any(feature = "dx12", feature = "metal", feature = "vulkan"))
+))]
+use gfx_backend_empty as back;
 #[cfg(feature = "metal")]
 use gfx_backend_metal as back;
 #[cfg(feature = "vulkan")]
 use gfx_backend_vulkan as back;
 
+#[cfg(not(any(
+    feature = "dx12",
+    feature = "metal",
+    feature = "vulkan",
+    feature = "empty"
+)))]
+compile_error!("No backend picked. Build with one of the vulkan, metal, dx12 or empty features.");
+
+/// The gfx-hal backend this was built with, picked by feature. A real one is used
+/// over `empty`, which is on by default.
+#[cfg(feature = "dx12")]
+pub const BACKEND: &str = "dx12";
+#[cfg(feature = "metal")]
+pub const BACKEND: &str = "metal";
+#[cfg(feature = "vulkan")]
+pub const BACKEND: &str = "vulkan";
+#[cfg(all(
+    feature = "empty",
+    not(any(feature = "dx12", feature = "metal", feature = "vulkan"))
+))]
+pub const BACKEND: &str = "empty";
+
+mod alloc;
+mod camera;
+mod capture;
+mod config;
+mod debug_view;
+mod depth;
+mod error;
+mod frame;
+mod mesh;
+mod msaa;
+mod pipeline;
+mod pipeline_cache;
+mod pixels;
+mod push;
+mod readback;
+mod reflect;
+mod resource;
+mod shaders;
+mod slots;
+mod target;
+mod texture;
+mod upload;
+
+pub use self::alloc::MemoryStats;
+pub use self::camera::{perspective, Camera};
+pub use self::config::{AdapterChoice, PresentConfig, RendererConfig};
+pub use self::debug_view::DebugView;
+pub use self::error::RendererError;
+pub use self::mesh::{BlockVertex, Index, MeshHandle, Vertex, VertexLayout};
+pub use self::pipeline::{BlendMode, PipelineBuilder, PipelineDesc, PipelineHandle};
+pub use self::pixels::RgbaImage;
+pub use self::push::{DrawConstants, PushConstants};
+pub use self::shaders::{ShaderSet, PLAIN_SHADERS};
+
+use self::alloc::Allocator;
+use self::camera::FrameUniforms;
+use self::capture::Capture;
+use self::depth::{DepthBuffer, DEPTH_TEST_ON};
+use self::frame::{FrameContext, UploadBuffer};
+use self::mesh::Mesh;
+use self::msaa::ColorBuffer;
+use self::pipeline::{Pipeline, ReflectedShaders};
+use self::pipeline_cache::PIPELINE_CACHE_PATH;
+use self::reflect::ShaderInterface;
+use self::resource::DeviceHandle;
+#[cfg(feature = "runtime-shaders")]
+use self::shaders::ShaderWatcher;
+use self::shaders::{ShaderCompiler, BUILT_IN_SHADERS};
+use self::slots::Slots;
+use self::target::{OffscreenTarget, Target, WindowTarget};
+use self::texture::{TextureArray, MAX_ANISOTROPY};
+use self::upload::Uploader;
+
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
-use shaderc;
 use winit::Window;
 
 use arrayvec::ArrayVec;
 
-#[allow(unused_imports)]
-use core::mem::ManuallyDrop;
+use std::{
+    collections::HashMap,
+    panic,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 #[allow(unused_imports)]
 use gfx_hal::{
-    adapter::*, command::*, device::Device, format::*, image::*, memory::*, pass::*, pass::*,
-    pool::*, pso::*, queue::*, window::*, Backend, Features, Gpu, Graphics, Instance, Primitive,
+    adapter::*, command::*, device::Device, format::*, image::*, memory::*, pass::*, pool::*,
+    pso::*, queue::*, window::*, Backend, Features, Gpu, Graphics, Instance, Primitive,
     QueueFamily, Surface,
 };
 
 pub struct HalState {
-    buffer: ManuallyDrop<<back::Backend as Backend>::Buffer>,
-    memory: ManuallyDrop<<back::Backend as Backend>::Memory>,
-    descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout>,
-    pipeline_layout: ManuallyDrop<<back::Backend as Backend>::PipelineLayout>,
-    graphics_pipeline: ManuallyDrop<<back::Backend as Backend>::GraphicsPipeline>,
-    requirements: Requirements,
+    /// What every pipeline's layout is made with, besides its push constants.
+    descriptor_set_layouts: Vec<resource::DescriptorSetLayout>,
+    _descriptor_pool: resource::DescriptorPool,
+    /// Bound at set 0, binding 1 of every frame. A single white layer until textures
+    /// are loaded.
+    block_textures: TextureArray,
+    sampler: resource::Sampler,
+    camera: Camera,
+    /// When the `HalState` was made, which `FrameUniforms::time` counts from.
+    started: Instant,
+    /// What the descriptor set layouts were made from. Every pipeline's shaders have to
+    /// fit in it.
+    shader_interface: ShaderInterface,
+    shader_compiler: ShaderCompiler,
+    /// Every `ShaderSet` pipelines have been built from, compiled and reflected, so
+    /// pipelines sharing shaders don't compile them again.
+    reflected_shaders: HashMap<ShaderSet, ReflectedShaders>,
+    /// Every pipeline is built through this, and it's saved to `PIPELINE_CACHE_PATH`
+    /// on drop.
+    pipeline_cache: resource::PipelineCache,
+    /// The named pipelines, and one for each vertex layout meshes were created with
+    /// without naming one. The first one draws `Triangle`s. Pipelines are never
+    /// removed, so `PipelineHandle`s index into this.
+    pipelines: Vec<Pipeline>,
+    #[cfg(feature = "runtime-shaders")]
+    shader_watcher: ShaderWatcher,
+    depth_test: DepthTest,
+    /// Every pipeline is built with this applied, see `set_debug_view`.
+    debug_view: DebugView,
+    /// Every pipeline draws lines rather than filled polygons while set.
+    wireframe: bool,
+    meshes: Slots<Mesh>,
     current_frame: usize,
-    frames_in_flight: usize,
-    in_flight_fences: Vec<<back::Backend as Backend>::Fence>,
-    render_finished_semaphores: Vec<<back::Backend as Backend>::Semaphore>,
-    image_available_semaphores: Vec<<back::Backend as Backend>::Semaphore>,
-    command_buffers: Vec<CommandBuffer<back::Backend, Graphics, MultiShot, Primary>>,
-    command_pool: ManuallyDrop<CommandPool<back::Backend, Graphics>>,
-    framebuffers: Vec<<back::Backend as Backend>::Framebuffer>,
-    image_views: Vec<(<back::Backend as Backend>::ImageView)>,
-    render_pass: ManuallyDrop<<back::Backend as Backend>::RenderPass>,
+    frames: Vec<FrameContext>,
+    /// The frame that last rendered into each swapchain image, so we never record
+    /// into an image another frame is still using.
+    images_in_flight: Vec<Option<usize>>,
+    capture: Capture,
+    command_pool: resource::CommandPool,
+    uploader: Uploader,
+    framebuffers: Vec<resource::Framebuffer>,
+    image_views: Vec<resource::ImageView>,
+    /// `None` while the window is minimized, like the swapchain.
+    depth_buffer: Option<DepthBuffer>,
+    /// What gets drawn into and resolved from with MSAA on. `None` without, and while
+    /// the window is minimized.
+    color_buffer: Option<ColorBuffer>,
+    /// Samples per pixel, 1 with MSAA off.
+    samples: NumSamples,
+    /// A bit for every sample count the device can do.
+    supported_samples: NumSamples,
+    depth_format: Format,
+    /// The optional features the device was opened with.
+    features: Features,
+    /// What new swapchains are made to follow.
+    present: PresentConfig,
+    render_pass: resource::RenderPass,
     render_area: Rect,
     queue_group: QueueGroup<back::Backend, Graphics>,
-    swapchain: ManuallyDrop<<back::Backend as Backend>::Swapchain>,
-    device: ManuallyDrop<back::Device>,
+    target: Target,
+    allocator: Allocator,
+    device: DeviceHandle,
     _adapter: Adapter<back::Backend>,
-    _surface: <back::Backend as Backend>::Surface,
-    _instance: ManuallyDrop<back::Instance>,
+    _instance: back::Instance,
 }
 
 impl HalState {
-    pub fn new(window: &Window, name: &str) -> Result<Self, &'static str> {
-        let instance = back::Instance::create(name, 1);
-        let mut surface = instance.create_surface(window);
+    /// Draws to `window`, on the adapter `config` ranks best of those that can.
+    pub fn new(window: &Window, name: &str, config: RendererConfig) -> Result<Self, RendererError> {
+        let instance = Self::create_instance(name)?;
+        let surface = instance.create_surface(window);
+        let window_extent = Self::window_extent(window)?;
+        Self::build(instance, Some(surface), window_extent, config)
+    }
 
-        let adapter = instance
-            .enumerate_adapters()
+    /// Renders into an offscreen image of the given size instead of a window, so
+    /// frames can be produced without a display and read back with `read_pixels`.
+    /// Uses the adapter `config` ranks best, like `new`.
+    pub fn new_headless(
+        name: &str,
+        width: u32,
+        height: u32,
+        config: RendererConfig,
+    ) -> Result<Self, RendererError> {
+        if width == 0 || height == 0 {
+            return Err(RendererError::InvalidArgument(
+                "Headless targets can't be empty!",
+            ));
+        }
+        let instance = Self::create_instance(name)?;
+        Self::build(instance, None, Extent2D { width, height }, config)
+    }
+
+    /// Fails straight away with the empty backend, which can't render.
+    ///
+    /// The backends panic rather than fail when the graphics API can't be loaded, which
+    /// is what machines without a GPU driver get. Those panics are caught, so building
+    /// with `panic = "abort"` isn't supported.
+    fn create_instance(name: &str) -> Result<back::Instance, RendererError> {
+        if BACKEND == "empty" {
+            return Err(RendererError::NoAdapter(
+                "Built without a backend! Enable the vulkan, metal or dx12 feature.",
+            ));
+        }
+        // The Vulkan loader is loaded up front and kept, so its absence, the usual
+        // case, can be checked for without panicking.
+        #[cfg(feature = "vulkan")]
+        {
+            if back::VK_ENTRY.is_err() {
+                return Err(RendererError::NoAdapter("Couldn't load the Vulkan loader!"));
+            }
+        }
+        // The hook is swapped out meanwhile so nothing gets printed for a caught panic.
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let instance = panic::catch_unwind(|| back::Instance::create(name, 1));
+        panic::set_hook(hook);
+        instance.map_err(|_| RendererError::NoAdapter("Couldn't load the graphics API!"))
+    }
+
+    /// Sets everything up around either a window surface or, without one, an
+    /// offscreen image of `extent`.
+    fn build(
+        instance: back::Instance,
+        surface: Option<<back::Backend as Backend>::Surface>,
+        extent: Extent2D,
+        config: RendererConfig,
+    ) -> Result<Self, RendererError> {
+        let frames_in_flight = config.frames_in_flight;
+        if frames_in_flight == 0 {
+            return Err(RendererError::InvalidArgument(
+                "Need at least one frame in flight!",
+            ));
+        }
+        let can_present = |qf: &<back::Backend as Backend>::QueueFamily| match &surface {
+            Some(surface) => surface.supports_queue_family(qf),
+            None => true,
+        };
+        let usable = |a: &Adapter<back::Backend>| {
+            a.queue_families
+                .iter()
+                .any(|qf| qf.supports_graphics() && can_present(qf))
+        };
+
+        let adapters = instance.enumerate_adapters();
+        for (index, adapter) in adapters.iter().enumerate() {
+            let note = if usable(adapter) {
+                ""
+            } else {
+                ", can't draw here"
+            };
+            info!(
+                "Adapter {}: {} ({:?}){}",
+                index, adapter.info.name, adapter.info.device_type, note
+            );
+        }
+        let (index, adapter) = adapters
             .into_iter()
-            .find(|a| {
-                a.queue_families
-                    .iter()
-                    .any(|qf| qf.supports_graphics() && surface.supports_queue_family(qf))
-            })
-            .ok_or("Couldn't find a graphical Adapter!")?;
+            .enumerate()
+            .filter(|(_, adapter)| usable(adapter))
+            .min_by_key(|(index, adapter)| config.adapter_rank(*index, &adapter.info))
+            .ok_or(RendererError::NoAdapter(
+                "Couldn't find a graphical Adapter!",
+            ))?;
+        if let Some(choice) = config.adapter.as_ref() {
+            if !config.is_chosen(index, &adapter.info) {
+                warn!("No adapter that can draw here matches {}", choice);
+            }
+        }
+        info!(
+            "Using the {} backend on adapter {}: {}",
+            BACKEND, index, adapter.info.name
+        );
+
+        // Optional features are done without on adapters that don't have them.
+        let features = config.features & adapter.physical_device.features();
+        if features != config.features {
+            info!("Going without {:?}", config.features - features);
+        }
 
-        let (mut device, queue_group) = {
+        let (device, mut queue_group) = {
             let queue_family = adapter
                 .queue_families
                 .iter()
-                .find(|qf| qf.supports_graphics() && surface.supports_queue_family(qf))
-                .ok_or("Couldn't find a QueueFamily with graphics!")?;
+                .find(|qf| qf.supports_graphics() && can_present(qf))
+                .ok_or(RendererError::Setup(
+                    "Couldn't find a QueueFamily with graphics!",
+                ))?;
             let Gpu { device, mut queues } = unsafe {
                 adapter
                     .physical_device
-                    .open(&[(&queue_family, &[1.0; 1])], Features::empty())
-                    .map_err(|_| "Couldn't open the PhysicalDevice!")?
-            };
-            let queue_group = queues
-                .take::<Graphics>(queue_family.id())
-                .ok_or("Couldn't take ownership of the QueueGroup!")?;
-            let _ = if queue_group.queues.len() > 0 {
-                Ok(())
-            } else {
-                Err("The QueueGroup did not have any CommandQueues available!")
-            }?;
-            (device, queue_group)
-        };
-
-        // Create swapchain stuff
-        let (swapchain, extent, backbuffer, format, frames_in_flight) =
-            Self::create_swapchain(window, &mut surface, &adapter, &device, None);
-
-        let (image_available_semaphores, render_finished_semaphores, in_flight_fences) = {
-            let mut image_available_semaphores: Vec<<back::Backend as Backend>::Semaphore> = vec![];
-            let mut render_finished_semaphores: Vec<<back::Backend as Backend>::Semaphore> = vec![];
-            let mut in_flight_fences: Vec<<back::Backend as Backend>::Fence> = vec![];
-            for _ in 0..frames_in_flight {
-                in_flight_fences.push(
-                    device
-                        .create_fence(true)
-                        .map_err(|_| "Could not create a fence!")?,
-                );
-                image_available_semaphores.push(
-                    device
-                        .create_semaphore()
-                        .map_err(|_| "Could not create a semaphore!")?,
-                );
-                render_finished_semaphores.push(
-                    device
-                        .create_semaphore()
-                        .map_err(|_| "Could not create a semaphore!")?,
-                );
-            }
-            (
-                image_available_semaphores,
-                render_finished_semaphores,
-                in_flight_fences,
-            )
-        };
-
-        let render_pass = {
-            let color_attachment = Attachment {
-                format: Some(format),
-                samples: 1,
-                ops: AttachmentOps {
-                    load: AttachmentLoadOp::Clear,
-                    store: AttachmentStoreOp::Store,
-                },
-                stencil_ops: AttachmentOps::DONT_CARE,
-                layouts: Layout::Undefined..Layout::Present,
+                    .open(&[(queue_family, &[1.0; 1])], features)?
             };
-            let subpass = SubpassDesc {
-                colors: &[(0, Layout::ColorAttachmentOptimal)],
-                depth_stencil: None,
-                inputs: &[],
-                resolves: &[],
-                preserves: &[],
-            };
-            unsafe {
-                device
-                    .create_render_pass(&[color_attachment], &[subpass], &[])
-                    .map_err(|_| "Couldn't create a render pass!")?
+            let queue_group =
+                queues
+                    .take::<Graphics>(queue_family.id())
+                    .ok_or(RendererError::Setup(
+                        "Couldn't take ownership of the QueueGroup!",
+                    ))?;
+            if queue_group.queues.is_empty() {
+                return Err(RendererError::Setup(
+                    "The QueueGroup did not have any CommandQueues available!",
+                ));
             }
+            (DeviceHandle::new(device), queue_group)
         };
 
-        let image_views: Vec<_> = backbuffer
-            .into_iter()
-            .map(|image| unsafe {
-                device
-                    .create_image_view(
-                        &image,
-                        ViewKind::D2,
-                        format,
-                        Swizzle::NO,
-                        SubresourceRange {
-                            aspects: Aspects::COLOR,
-                            levels: 0..1,
-                            layers: 0..1,
-                        },
-                    )
-                    .map_err(|_| "Couldn't create the image_view for the image!")
-            })
-            .collect::<Result<Vec<_>, &str>>()?;
+        let mut allocator = Allocator::new(
+            device.clone(),
+            adapter.physical_device.memory_properties().memory_types,
+        );
 
-        let framebuffers: Vec<<back::Backend as Backend>::Framebuffer> = {
-            image_views
-                .iter()
-                .map(|image_view| unsafe {
-                    device
-                        .create_framebuffer(
-                            &render_pass,
-                            vec![image_view],
-                            Extent {
-                                width: extent.width as u32,
-                                height: extent.height as u32,
-                                depth: 1,
-                            },
-                        )
-                        .map_err(|_| "Failed to create a framebuffer!")
-                })
-                .collect::<Result<Vec<_>, &str>>()?
-        };
-
-        let mut command_pool = unsafe {
-            device
-                .create_command_pool_typed(&queue_group, CommandPoolCreateFlags::RESET_INDIVIDUAL)
-                .map_err(|_| "Could not create the raw command pool!")?
+        // Create swapchain stuff, or the image standing in for it
+        let (target, extent) = match surface {
+            Some(mut surface) => {
+                let (swapchain, images, swapchain_config) = Self::create_swapchain(
+                    extent,
+                    &mut surface,
+                    &adapter,
+                    &device,
+                    config.present,
+                    None,
+                )?;
+                let target = WindowTarget {
+                    swapchain: Some(resource::Swapchain::new(&device, swapchain)),
+                    surface,
+                    images,
+                    format: swapchain_config.format,
+                    usage: swapchain_config.image_usage,
+                    present_mode: swapchain_config.present_mode,
+                    suboptimal: false,
+                    window_extent: extent,
+                };
+                (Target::Window(target), swapchain_config.extent)
+            }
+            None => {
+                let target = OffscreenTarget::new(&device, &mut allocator, extent)?;
+                (Target::Offscreen(target), extent)
+            }
         };
 
-        let command_buffers: Vec<_> = framebuffers
-            .iter()
-            .map(|_| command_pool.acquire_command_buffer())
-            .collect();
+        let depth_format = depth::pick_depth_format(&adapter.physical_device)?;
+        let supported_samples = msaa::supported_samples(&adapter.physical_device.limits());
+        let samples = msaa::clamp_samples(config.msaa, supported_samples);
+        if samples != config.msaa {
+            info!("Asked for {}x MSAA, using {}x", config.msaa, samples);
+        }
 
-        let (descriptor_set_layouts, pipeline_layout, graphics_pipeline) =
-            Self::create_pipeline(&mut device, extent, &render_pass)?;
+        let render_pass = Self::create_render_pass(&device, &target, depth_format, samples)?;
 
-        const F32_XY_TRIANGLE: u64 = (std::mem::size_of::<f32>() * 2 * 3) as u64;
-        let mut buffer = unsafe {
-            device
-                .create_buffer(F32_XY_TRIANGLE, gfx_hal::buffer::Usage::VERTEX)
-                .map_err(|_| "Couldn't create a buffer for the vertices")?
+        let image_views = Self::create_image_views(&device, &target)?;
+        let depth_buffer =
+            DepthBuffer::new(&device, &mut allocator, extent, depth_format, samples)?;
+        let color_buffer = if samples > 1 {
+            Some(ColorBuffer::new(
+                &device,
+                &mut allocator,
+                extent,
+                target.format(),
+                samples,
+            )?)
+        } else {
+            None
         };
+        let framebuffers = Self::create_framebuffers(
+            &device,
+            &render_pass,
+            &image_views,
+            &depth_buffer.view,
+            color_buffer
+                .as_ref()
+                .map(|color_buffer| &*color_buffer.view),
+            extent,
+        )?;
 
-        let requirements = unsafe { device.get_buffer_requirements(&buffer) };
-        let memory_type_id = adapter
-            .physical_device
-            .memory_properties()
-            .memory_types
-            .iter()
-            .enumerate()
-            .find(|&(id, memory_type)| {
-                requirements.type_mask & (1 << id) != 0
-                    && memory_type
-                        .properties
-                        .contains(gfx_hal::memory::Properties::CPU_VISIBLE)
-            })
-            .map(|(id, _)| MemoryTypeId(id))
-            .ok_or("Couldn't find a memory type to support the vertex buffer!")?;
-        let memory = unsafe {
-            let memory = device
-                .allocate_memory(memory_type_id, requirements.size)
-                .map_err(|_| "Couldn't allocate vertex buffer memory")?;
+        let mut command_pool = resource::CommandPool::new(&device, unsafe {
             device
-                .bind_buffer_memory(&memory, 0, &mut buffer)
-                .map_err(|_| "Couldn't bind the buffer memory!")?;
-            memory
+                .create_command_pool_typed(&queue_group, CommandPoolCreateFlags::RESET_INDIVIDUAL)?
+        });
+
+        let mut uploader = Uploader::new(&device, &mut allocator, &queue_group)?;
+        let anisotropic = if features.contains(Features::SAMPLER_ANISOTROPY) {
+            let limits = adapter.physical_device.limits();
+            Anisotropic::On(limits.max_sampler_anisotropy.min(MAX_ANISOTROPY) as u8)
+        } else {
+            Anisotropic::Off
         };
+        let sampler = texture::create_sampler(&device, anisotropic)?;
+        let block_textures = TextureArray::new(
+            &device,
+            &mut allocator,
+            &mut uploader,
+            &mut queue_group.queues[0],
+            &[RgbaImage::new(1, 1, vec![255; 4])],
+        )?;
+
+        let mut shader_compiler = ShaderCompiler::new()?;
+        let (shader_interface, reflected_shaders) =
+            Self::reflect_built_in_shaders(&mut shader_compiler)?;
+        let descriptor_set_layouts =
+            Self::create_descriptor_set_layouts(&device, &shader_interface)?;
+        let mut descriptor_pool = resource::DescriptorPool::new(&device, unsafe {
+            device.create_descriptor_pool(
+                frames_in_flight,
+                shader_interface.descriptor_ranges(frames_in_flight),
+                DescriptorPoolCreateFlags::empty(),
+            )?
+        });
+
+        let frames = (0..frames_in_flight)
+            .map(|_| {
+                FrameContext::new(
+                    &device,
+                    &mut command_pool,
+                    &mut allocator,
+                    &mut descriptor_pool,
+                    &descriptor_set_layouts[0],
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for frame in &frames {
+            unsafe { frame.write_block_textures(&device, &block_textures.view, &sampler) };
+        }
+        let pipeline_cache = resource::PipelineCache::new(&device, unsafe {
+            let data = pipeline_cache::load(Path::new(PIPELINE_CACHE_PATH), &adapter.info);
+            device.create_pipeline_cache(data.as_deref())?
+        });
+        let triangle_desc = PipelineDesc::for_vertex_layout(<[f32; 2]>::layout(), DEPTH_TEST_ON);
+        let triangle_pipeline = Pipeline::new(
+            &device,
+            &render_pass,
+            samples,
+            &descriptor_set_layouts,
+            &pipeline_cache,
+            &shader_interface,
+            &reflected_shaders[&triangle_desc.shaders],
+            None,
+            triangle_desc,
+        )?;
 
         Ok(Self {
-            _instance: ManuallyDrop::new(instance),
-            _surface: surface,
+            _instance: instance,
             _adapter: adapter,
-            device: ManuallyDrop::new(device),
+            device,
             queue_group,
-            swapchain: ManuallyDrop::new(swapchain),
+            target,
+            allocator,
             render_area: extent.to_extent().rect(),
-            render_pass: ManuallyDrop::new(render_pass),
+            render_pass,
             image_views,
+            depth_buffer: Some(depth_buffer),
+            color_buffer,
+            samples,
+            supported_samples,
+            depth_format,
+            features,
+            present: config.present,
+            images_in_flight: vec![None; framebuffers.len()],
             framebuffers,
-            command_pool: ManuallyDrop::new(command_pool),
-            command_buffers,
-            image_available_semaphores,
-            render_finished_semaphores,
-            in_flight_fences,
-            frames_in_flight,
+            command_pool,
+            uploader,
+            frames,
             current_frame: 0,
-            descriptor_set_layouts: descriptor_set_layouts,
-            pipeline_layout: ManuallyDrop::new(pipeline_layout),
-            graphics_pipeline: ManuallyDrop::new(graphics_pipeline),
-            buffer: ManuallyDrop::new(buffer),
-            memory: ManuallyDrop::new(memory),
-            requirements: requirements,
+            capture: Capture::default(),
+            descriptor_set_layouts,
+            _descriptor_pool: descriptor_pool,
+            block_textures,
+            sampler,
+            camera: Camera::default(),
+            started: Instant::now(),
+            shader_interface,
+            shader_compiler,
+            reflected_shaders,
+            pipeline_cache,
+            pipelines: vec![triangle_pipeline],
+            #[cfg(feature = "runtime-shaders")]
+            shader_watcher: ShaderWatcher::default(),
+            depth_test: DEPTH_TEST_ON,
+            debug_view: DebugView::Off,
+            wireframe: false,
+            meshes: Slots::default(),
         })
     }
 
-    pub fn draw_clear_frame(
-        &mut self,
-        color: [f32; 4],
-    ) -> Result<Option<Suboptimal>, &'static str> {
-        // SETUP FOR THIS FRAME
-        // Advance frame, get sempahors to determine if render is done.
-        let image_available = &self.image_available_semaphores[self.current_frame];
-        let render_finished = &self.render_finished_semaphores[self.current_frame];
-        self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
-
-        let (i_u32, i_usize) = unsafe {
-            let check = self
-                .swapchain
-                .acquire_image(std::u64::MAX, Some(image_available), None)
-                .unwrap();
-            (check.0, check.0 as usize)
-        };
-
-        // Wait until we can render
-        let flight_fence = &self.in_flight_fences[i_usize];
-        unsafe {
-            self.device
-                .wait_for_fence(flight_fence, core::u64::MAX)
-                .map_err(|_| "Failed to wait on the fence!")?;
-            self.device
-                .reset_fence(flight_fence)
-                .map_err(|_| "Couldn't reset the fence!")?;
-        }
+    /// The single subpass everything is drawn in, clearing one of `target`'s images and
+    /// the depth buffer. Pipelines are built for this, so they're rebuilt whenever it is.
+    ///
+    /// With more than one sample the color buffer is cleared instead, and resolved
+    /// into the target's image, attachment 2, at the end.
+    fn create_render_pass(
+        device: &DeviceHandle,
+        target: &Target,
+        depth_format: Format,
+        samples: NumSamples,
+    ) -> Result<resource::RenderPass, RendererError> {
+        let multisampled = samples > 1;
+        let color_attachment = Attachment {
+            format: Some(target.format()),
+            samples,
+            ops: AttachmentOps {
+                load: AttachmentLoadOp::Clear,
+                store: if multisampled {
+                    AttachmentStoreOp::DontCare
+                } else {
+                    AttachmentStoreOp::Store
+                },
+            },
+            stencil_ops: AttachmentOps::DONT_CARE,
+            layouts: if multisampled {
+                Layout::Undefined..Layout::ColorAttachmentOptimal
+            } else {
+                Layout::Undefined..target.final_layout()
+            },
+        };
+        let resolve_attachment = Attachment {
+            format: Some(target.format()),
+            samples: 1,
+            ops: AttachmentOps {
+                load: AttachmentLoadOp::DontCare,
+                store: AttachmentStoreOp::Store,
+            },
+            stencil_ops: AttachmentOps::DONT_CARE,
+            layouts: Layout::Undefined..target.final_layout(),
+        };
+        let depth_attachment = Attachment {
+            format: Some(depth_format),
+            samples,
+            ops: AttachmentOps {
+                load: AttachmentLoadOp::Clear,
+                store: AttachmentStoreOp::DontCare,
+            },
+            stencil_ops: AttachmentOps::DONT_CARE,
+            layouts: Layout::Undefined..Layout::DepthStencilAttachmentOptimal,
+        };
+        let subpass = SubpassDesc {
+            colors: &[(0, Layout::ColorAttachmentOptimal)],
+            depth_stencil: Some(&(1, Layout::DepthStencilAttachmentOptimal)),
+            inputs: &[],
+            resolves: if multisampled {
+                &[(2, Layout::ColorAttachmentOptimal)]
+            } else {
+                &[]
+            },
+            preserves: &[],
+        };
+        // Every frame shares the one depth image, and with MSAA the one multisampled
+        // color image, so the previous frame has to be done writing them before this
+        // one clears them.
+        let dependency = SubpassDependency {
+            passes: SubpassRef::External..SubpassRef::Pass(0),
+            stages: (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::LATE_FRAGMENT_TESTS)
+                ..(PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS),
+            accesses: (Access::COLOR_ATTACHMENT_WRITE | Access::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                ..(Access::COLOR_ATTACHMENT_WRITE
+                    | Access::DEPTH_STENCIL_ATTACHMENT_READ
+                    | Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        };
+        let attachments = if multisampled {
+            vec![color_attachment, depth_attachment, resolve_attachment]
+        } else {
+            vec![color_attachment, depth_attachment]
+        };
+        let render_pass =
+            unsafe { device.create_render_pass(attachments, &[subpass], &[dependency])? };
+        Ok(resource::RenderPass::new(device, render_pass))
+    }
+
+    fn create_image_views(
+        device: &DeviceHandle,
+        target: &Target,
+    ) -> Result<Vec<resource::ImageView>, RendererError> {
+        target
+            .images()
+            .iter()
+            .map(|image| {
+                let view = unsafe {
+                    device.create_image_view(
+                        image,
+                        ViewKind::D2,
+                        target.format(),
+                        Swizzle::NO,
+                        SubresourceRange {
+                            aspects: Aspects::COLOR,
+                            levels: 0..1,
+                            layers: 0..1,
+                        },
+                    )?
+                };
+                Ok(resource::ImageView::new(device, view))
+            })
+            .collect()
+    }
+
+    /// A framebuffer for each of `image_views`, which are drawn into directly unless
+    /// there's a multisampled `color_view` to resolve from.
+    fn create_framebuffers(
+        device: &DeviceHandle,
+        render_pass: &<back::Backend as Backend>::RenderPass,
+        image_views: &[resource::ImageView],
+        depth_view: &<back::Backend as Backend>::ImageView,
+        color_view: Option<&<back::Backend as Backend>::ImageView>,
+        extent: Extent2D,
+    ) -> Result<Vec<resource::Framebuffer>, RendererError> {
+        image_views
+            .iter()
+            .map(|image_view| {
+                let attachments = match color_view {
+                    Some(color_view) => vec![color_view, depth_view, &**image_view],
+                    None => vec![&**image_view, depth_view],
+                };
+                let framebuffer = unsafe {
+                    device.create_framebuffer(
+                        render_pass,
+                        attachments,
+                        Extent {
+                            width: extent.width,
+                            height: extent.height,
+                            depth: 1,
+                        },
+                    )?
+                };
+                Ok(resource::Framebuffer::new(device, framebuffer))
+            })
+            .collect()
+    }
+
+    pub fn draw_clear_frame(&mut self, color: [f32; 4]) -> Result<(), RendererError> {
+        let (frame, image) = match self.begin_frame()? {
+            Some(acquired) => acquired,
+            None => return Ok(()),
+        };
 
         //Get a command buffer and fill it with the command.
         unsafe {
-            let buffer = &mut self.command_buffers[i_usize];
-            let clear_values = [ClearValue::Color(ClearColor::Float(color))];
+            let buffer = &mut self.frames[frame].command_buffer;
+            let clear_values = [
+                ClearValue::Color(ClearColor::Float(color)),
+                ClearValue::DepthStencil(ClearDepthStencil(1.0, 0)),
+            ];
             buffer.begin(false);
             buffer.begin_render_pass_inline(
                 &self.render_pass,
-                &self.framebuffers[i_usize],
+                &self.framebuffers[image as usize],
                 self.render_area,
                 clear_values.iter(),
             );
             buffer.finish();
         }
 
-        let command_buffers = &self.command_buffers[i_usize..=i_usize];
-        let wait_semaphores: ArrayVec<[_; 1]> =
-            [(image_available, PipelineStage::COLOR_ATTACHMENT_OUTPUT)].into();
-        let signal_semaphores: ArrayVec<[_; 1]> = [render_finished].into();
-        let present_wait_semaphores: ArrayVec<[_; 1]> = [render_finished].into();
+        self.end_frame(frame, image)
+    }
+
+    /// Waits until the next frame context is free and acquires an image to draw into.
+    ///
+    /// Returns the frame context index and the image index, or `None` if this frame
+    /// should be skipped. Once this returns an image the frame has to be ended, since
+    /// its fence is reset and only signalled by submitting.
+    fn begin_frame(&mut self) -> Result<Option<(usize, SwapImageIndex)>, RendererError> {
+        let frame = self.wait_for_frame()?;
+        self.acquire_frame(frame)
+    }
+
+    /// Waits until the next frame context is free and returns its index. Its upload
+    /// buffer can be written from then on. Calling this again before `acquire_frame`
+    /// is fine.
+    fn wait_for_frame(&mut self) -> Result<usize, RendererError> {
+        // Uploads queued since the last frame go first, so this frame sees them.
+        self.uploader
+            .flush(&self.device, &mut self.queue_group.queues[0])?;
+        self.uploader.poll(&self.device)?;
+
+        let frame = self.current_frame;
+        // The frame's semaphores, command buffer and upload buffer are free once its
+        // previous submission is done.
+        unsafe {
+            self.device
+                .wait_for_fence(&self.frames[frame].fence, u64::MAX)?;
+        }
+        // So is anything dropped while that submission could still have been using it.
+        self.device
+            .collect(self.frames[frame].submitted, &mut self.allocator);
+        let uniforms = FrameUniforms {
+            view: self.camera.view,
+            projection: self.camera.projection,
+            time: self.started.elapsed().as_secs_f32(),
+            _padding: [0.0; 3],
+        };
+        self.allocator
+            .write(self.frames[frame].uniforms.allocation(), 0, &[uniforms])?;
+        Ok(frame)
+    }
+
+    /// Acquires an image for `frame`, the one `wait_for_frame` returned, and resets its
+    /// fence. See `begin_frame`.
+    fn acquire_frame(
+        &mut self,
+        frame: usize,
+    ) -> Result<Option<(usize, SwapImageIndex)>, RendererError> {
+        let image = match self.acquire_image(frame)? {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+
+        // With more frames in flight than images (or an out of order acquire) the image
+        // can still be in use by a different frame.
+        if let Some(previous) = self.images_in_flight[image as usize] {
+            if previous != frame {
+                unsafe {
+                    self.device
+                        .wait_for_fence(&self.frames[previous].fence, u64::MAX)?;
+                }
+            }
+        }
+        self.images_in_flight[image as usize] = Some(frame);
+
+        unsafe {
+            self.device.reset_fence(&self.frames[frame].fence)?;
+        }
+        self.current_frame = (frame + 1) % self.frames.len();
+        Ok(Some((frame, image)))
+    }
+
+    /// Submits the frame's command buffer, writes out any captures waiting on this
+    /// frame and presents the image.
+    fn end_frame(&mut self, frame: usize, image: SwapImageIndex) -> Result<(), RendererError> {
+        self.frames[frame].submitted = self.device.frame_submitted();
+        let context = &self.frames[frame];
+        let command_buffers: ArrayVec<[_; 1]> = [&context.command_buffer].into();
+        let mut wait_semaphores: ArrayVec<[_; 1]> = ArrayVec::new();
+        let mut signal_semaphores: ArrayVec<[_; 1]> = ArrayVec::new();
+        // Offscreen frames have nothing to acquire or present, so nothing to wait on either.
+        if let Target::Window(_) = self.target {
+            wait_semaphores.push((
+                &*context.image_available,
+                PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+            ));
+            signal_semaphores.push(&*context.render_finished);
+        }
         let submission = Submission {
             command_buffers,
             wait_semaphores,
             signal_semaphores,
         };
-        let the_command_queue = &mut self.queue_group.queues[0];
         unsafe {
-            the_command_queue.submit(submission, Some(flight_fence));
-            self.swapchain
-                .present(the_command_queue, i_u32, present_wait_semaphores)
-                .map_err(|_| "Failed to present into the swapchain!")
+            self.queue_group.queues[0].submit(submission, Some(&*context.fence));
+        }
+        // The image belongs to the presentation engine once presented, so copy it first.
+        if self.capture.is_pending() {
+            self.write_captures(image);
+        }
+        self.present_image(frame, image)
+    }
+
+    /// Acquires the next swapchain image, signalling the frame's image available semaphore.
+    /// Offscreen targets only have the one image, which is always ready.
+    ///
+    /// Returns `None` if there is nothing to draw into this frame, either because the
+    /// window is minimized or because the swapchain was out of date and just got rebuilt.
+    fn acquire_image(&mut self, frame: usize) -> Result<Option<SwapImageIndex>, RendererError> {
+        match &self.target {
+            Target::Offscreen(_) => return Ok(Some(0)),
+            Target::Window(window) if window.swapchain.is_none() => self.rebuild_swapchain()?,
+            Target::Window(_) => (),
+        }
+        let acquired = match &mut self.target {
+            Target::Window(WindowTarget {
+                swapchain: Some(swapchain),
+                ..
+            }) => unsafe {
+                swapchain.acquire_image(u64::MAX, Some(&*self.frames[frame].image_available), None)
+            },
+            _ => return Ok(None),
+        };
+        match acquired {
+            Ok((index, suboptimal)) => {
+                // Still presentable, so finish this frame and rebuild after presenting.
+                if let Target::Window(window) = &mut self.target {
+                    window.suboptimal |= suboptimal.is_some();
+                }
+                Ok(Some(index))
+            }
+            Err(AcquireError::OutOfDate) => {
+                self.rebuild_swapchain()?;
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Presents the image once the frame's render finished semaphore is signalled, and
+    /// rebuilds the swapchain if the surface told us it no longer matches.
+    fn present_image(&mut self, frame: usize, index: SwapImageIndex) -> Result<(), RendererError> {
+        let (presented, suboptimal) = match &self.target {
+            Target::Window(WindowTarget {
+                swapchain: Some(swapchain),
+                suboptimal,
+                ..
+            }) => unsafe {
+                let presented = swapchain.present(
+                    &mut self.queue_group.queues[0],
+                    index,
+                    Some(&*self.frames[frame].render_finished),
+                );
+                (presented, *suboptimal)
+            },
+            _ => return Ok(()),
+        };
+        match presented {
+            Ok(None) if !suboptimal => Ok(()),
+            Ok(_) | Err(PresentError::OutOfDate) => self.rebuild_swapchain(),
+            Err(e) => Err(e.into()),
         }
     }
 
+    /// Makes a swapchain for `surface` that follows `present` as far as the surface
+    /// allows, returning it with its images and what it was made with.
+    #[allow(clippy::type_complexity)]
     fn create_swapchain(
-        window: &Window,
+        window_extent: Extent2D,
         surface: &mut <back::Backend as Backend>::Surface,
         adapter: &Adapter<back::Backend>,
         device: &<back::Backend as Backend>::Device,
+        present: PresentConfig,
         old_swapchain: Option<<back::Backend as Backend>::Swapchain>,
-    ) -> (
-        <back::Backend as Backend>::Swapchain,
-        Extent2D,
-        Vec<<back::Backend as Backend>::Image>,
-        Format,
-        usize,
-    ) {
+    ) -> Result<
+        (
+            <back::Backend as Backend>::Swapchain,
+            Vec<<back::Backend as Backend>::Image>,
+            SwapchainConfig,
+        ),
+        RendererError,
+    > {
         let (caps, preferred_formats, present_modes) =
             surface.compatibility(&adapter.physical_device);
         info!("{:?}", caps);
@@ -338,15 +854,12 @@ impl HalState {
         info!("Present Modes: {:?}", present_modes);
         info!("Composite Alphas: {:?}", caps.composite_alpha);
         //
-        let present_mode = {
-            use gfx_hal::window::PresentMode::*;
-            [Mailbox, Fifo, Relaxed, Immediate]
-                .iter()
-                .cloned()
-                .find(|pm| present_modes.contains(pm))
-                .ok_or("No PresentMode values specified!")
-                .unwrap()
-        };
+        let present_mode = present
+            .present_modes()
+            .iter()
+            .cloned()
+            .find(|pm| present_modes.contains(pm))
+            .ok_or(RendererError::Setup("No PresentMode values specified!"))?;
         let composite_alpha = {
             use gfx_hal::window::CompositeAlpha;
             [
@@ -358,8 +871,7 @@ impl HalState {
             .iter()
             .cloned()
             .find(|ca| caps.composite_alpha.contains(*ca))
-            .ok_or("No CompositeAlpha values specified!")
-            .unwrap()
+            .ok_or(RendererError::Setup("No CompositeAlpha values specified!"))?
         };
         let format = match preferred_formats {
             None => Format::Rgba8Srgb,
@@ -370,37 +882,34 @@ impl HalState {
             {
                 Some(srgb_format) => srgb_format,
                 None => formats
-                    .get(0)
+                    .first()
                     .cloned()
-                    .ok_or("Preferred format list was empty!")
-                    .unwrap(),
+                    .ok_or(RendererError::Setup("Preferred format list was empty!"))?,
             },
         };
-        let extent = {
-            let window_client_area = window
-                .get_inner_size()
-                .ok_or("Window doesn't exist!")
-                .unwrap()
-                .to_physical(window.get_hidpi_factor());
-            Extent2D {
-                width: caps.extents.end.width.min(window_client_area.width as u32),
-                height: caps
-                    .extents
-                    .end
-                    .height
-                    .min(window_client_area.height as u32),
-            }
-        };
-        let image_count = if present_mode == PresentMode::Mailbox {
-            (caps.image_count.end - 1).min(caps.image_count.start.max(3))
-        } else {
-            (caps.image_count.end - 1).min(caps.image_count.start.max(2))
+        let extent = caps.current_extent.unwrap_or(Extent2D {
+            width: caps.extents.end.width.min(window_extent.width),
+            height: caps.extents.end.height.min(window_extent.height),
+        });
+        // The end of the range is the most images the surface takes, not one past it,
+        // and 0 when there's no limit.
+        let max_image_count = match caps.image_count.end {
+            0 => u32::MAX,
+            max => max,
         };
+        let image_count = present
+            .image_count()
+            .max(caps.image_count.start)
+            .min(max_image_count);
         let image_layers = 1;
         let image_usage = if caps.usage.contains(Usage::COLOR_ATTACHMENT) {
-            Usage::COLOR_ATTACHMENT
+            // Being able to copy out of the images is only needed for captures, so
+            // it's fine to go without.
+            Usage::COLOR_ATTACHMENT | (caps.usage & Usage::TRANSFER_SRC)
         } else {
-            Err("The Surface isn't capable of supporting color!").unwrap()
+            return Err(RendererError::Setup(
+                "The Surface isn't capable of supporting color!",
+            ));
         };
         let swapchain_config = SwapchainConfig {
             present_mode,
@@ -413,131 +922,834 @@ impl HalState {
         };
         info!("{:?}", swapchain_config);
         //
-        let (swapchain, backbuffer) = unsafe {
-            device
-                .create_swapchain(surface, swapchain_config, old_swapchain)
-                .map_err(|_| "Failed to create the swapchain!")
-                .unwrap()
-        };
-        (swapchain, extent, backbuffer, format, image_count as usize)
+        let (swapchain, backbuffer) =
+            unsafe { device.create_swapchain(surface, swapchain_config.clone(), old_swapchain)? };
+        Ok((swapchain, backbuffer, swapchain_config))
     }
 
-    pub fn recreate_swapchain(&mut self, window: &Window) {
-        self.device.wait_idle().unwrap();
-        unsafe {
-            self.command_pool.reset();
-            for framebuffer in self.framebuffers.drain(..) {
-                self.device.destroy_framebuffer(framebuffer);
-            }
-            for image_view in self.image_views.drain(..) {
-                self.device.destroy_image_view(image_view);
+    /// The client area of the window in physical pixels.
+    fn window_extent(window: &Window) -> Result<Extent2D, RendererError> {
+        let window_client_area = window
+            .get_inner_size()
+            .ok_or(RendererError::Setup("Window doesn't exist!"))?
+            .to_physical(window.get_hidpi_factor());
+        Ok(Extent2D {
+            width: window_client_area.width as u32,
+            height: window_client_area.height as u32,
+        })
+    }
+
+    /// Rebuilds the swapchain and everything sized after it, for example after a resize.
+    pub fn recreate_swapchain(&mut self, window: &Window) -> Result<(), RendererError> {
+        let window_extent = Self::window_extent(window)?;
+        if let Target::Window(target) = &mut self.target {
+            target.window_extent = window_extent;
+        }
+        self.rebuild_swapchain()
+    }
+
+    /// Rebuilds the swapchain at the last known window size. While the window is
+    /// minimized there is nothing to present to, so the swapchain is dropped and
+    /// frames are skipped until it has a size again.
+    fn rebuild_swapchain(&mut self) -> Result<(), RendererError> {
+        let window = match &mut self.target {
+            Target::Window(window) => window,
+            Target::Offscreen(_) => return Ok(()),
+        };
+        self.device.wait_idle_and_collect(&mut self.allocator)?;
+        unsafe { self.command_pool.reset() };
+        self.framebuffers.clear();
+        self.image_views.clear();
+        self.depth_buffer = None;
+        self.color_buffer = None;
+        window.suboptimal = false;
+        if window.window_extent.width == 0 || window.window_extent.height == 0 {
+            window.images.clear();
+            window.swapchain = None;
+            return Ok(());
+        }
+
+        // The old swapchain is consumed even if creation fails, and its images with it.
+        let old_swapchain = window.swapchain.take().map(resource::Swapchain::into_raw);
+        window.images.clear();
+        let (swapchain, images, swapchain_config) = Self::create_swapchain(
+            window.window_extent,
+            &mut window.surface,
+            &self._adapter,
+            &self.device,
+            self.present,
+            old_swapchain,
+        )?;
+        window.swapchain = Some(resource::Swapchain::new(&self.device, swapchain));
+        window.images = images;
+        let old_format = std::mem::replace(&mut window.format, swapchain_config.format);
+        window.usage = swapchain_config.image_usage;
+        window.present_mode = swapchain_config.present_mode;
+
+        // Without everything sized after it there's nothing to draw to, so the swapchain
+        // goes again and the next frame tries to make it once more. The old format is
+        // put back so the render pass is remade then too.
+        if let Err(e) = self.create_swapchain_attachments(
+            old_format != swapchain_config.format,
+            swapchain_config.extent,
+        ) {
+            if let Target::Window(window) = &mut self.target {
+                window.swapchain = None;
+                window.images.clear();
+                window.format = old_format;
             }
+            self.framebuffers.clear();
+            self.image_views.clear();
+            self.depth_buffer = None;
+            self.color_buffer = None;
+            self.images_in_flight.clear();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Makes the image views, attachments and framebuffers for a new swapchain of
+    /// `extent`, and the render pass too if its format changed.
+    fn create_swapchain_attachments(
+        &mut self,
+        format_changed: bool,
+        extent: Extent2D,
+    ) -> Result<(), RendererError> {
+        // Pipelines only work with render passes like the one they were built for, and
+        // a different image format makes a different render pass.
+        if format_changed {
+            self.rebuild_render_pass()?;
         }
-        let (swapchain, extent, backbuffer, format, _frames_in_flight) = unsafe {
-            use core::ptr::read;
-            let old_swapchain = ManuallyDrop::into_inner(read(&self.swapchain));
-            Self::create_swapchain(
-                window,
-                &mut self._surface,
-                &self._adapter,
+
+        self.image_views = Self::create_image_views(&self.device, &self.target)?;
+        self.create_attachments(extent)?;
+        self.images_in_flight = vec![None; self.framebuffers.len()];
+        self.render_area = extent.to_extent().rect();
+        Ok(())
+    }
+
+    /// Makes the render pass again, and every pipeline along with it.
+    fn rebuild_render_pass(&mut self) -> Result<(), RendererError> {
+        self.render_pass =
+            Self::create_render_pass(&self.device, &self.target, self.depth_format, self.samples)?;
+        self.rebuild_pipelines()
+    }
+
+    /// Builds every pipeline again from its own desc.
+    fn rebuild_pipelines(&mut self) -> Result<(), RendererError> {
+        let replacements = self
+            .pipelines
+            .iter()
+            .enumerate()
+            .map(|(i, pipeline)| (i, pipeline.desc.clone()))
+            .collect();
+        self.replace_pipelines(replacements)
+    }
+
+    /// Makes the depth and color buffers of `extent`, and framebuffers from them and
+    /// the image views.
+    fn create_attachments(&mut self, extent: Extent2D) -> Result<(), RendererError> {
+        let (depth_buffer, color_buffer) = self.create_buffers(self.samples, extent)?;
+        self.framebuffers = Self::create_framebuffers(
+            &self.device,
+            &self.render_pass,
+            &self.image_views,
+            &depth_buffer.view,
+            color_buffer
+                .as_ref()
+                .map(|color_buffer| &*color_buffer.view),
+            extent,
+        )?;
+        self.depth_buffer = Some(depth_buffer);
+        self.color_buffer = color_buffer;
+        Ok(())
+    }
+
+    /// The depth buffer of `extent` for `samples` per pixel, and with more than one the
+    /// color buffer that's resolved into the target.
+    fn create_buffers(
+        &mut self,
+        samples: NumSamples,
+        extent: Extent2D,
+    ) -> Result<(DepthBuffer, Option<ColorBuffer>), RendererError> {
+        let depth_buffer = DepthBuffer::new(
+            &self.device,
+            &mut self.allocator,
+            extent,
+            self.depth_format,
+            samples,
+        )?;
+        let color_buffer = if samples > 1 {
+            Some(ColorBuffer::new(
                 &self.device,
-                Some(old_swapchain),
-            )
+                &mut self.allocator,
+                extent,
+                self.target.format(),
+                samples,
+            )?)
+        } else {
+            None
+        };
+        Ok((depth_buffer, color_buffer))
+    }
+
+    /// Samples per pixel, 1 with MSAA off.
+    pub fn msaa(&self) -> NumSamples {
+        self.samples
+    }
+
+    /// Switches to `samples` per pixel, or the most the device can do below that,
+    /// rebuilding the render pass, pipelines and framebuffers. Returns the samples
+    /// per pixel now used. If any of them can't be made MSAA stays as it was.
+    pub fn set_msaa(&mut self, samples: NumSamples) -> Result<NumSamples, RendererError> {
+        let samples = msaa::clamp_samples(samples, self.supported_samples);
+        if samples == self.samples {
+            return Ok(samples);
+        }
+        self.device.wait_idle_and_collect(&mut self.allocator)?;
+        let render_pass =
+            Self::create_render_pass(&self.device, &self.target, self.depth_format, samples)?;
+        // A minimized window gets its attachments once it's back.
+        let attachments = match self.depth_buffer {
+            Some(_) => {
+                let extent = Extent2D {
+                    width: self.render_area.w as u32,
+                    height: self.render_area.h as u32,
+                };
+                let (depth_buffer, color_buffer) = self.create_buffers(samples, extent)?;
+                let framebuffers = Self::create_framebuffers(
+                    &self.device,
+                    &render_pass,
+                    &self.image_views,
+                    &depth_buffer.view,
+                    color_buffer
+                        .as_ref()
+                        .map(|color_buffer| &*color_buffer.view),
+                    extent,
+                )?;
+                Some((depth_buffer, color_buffer, framebuffers))
+            }
+            None => None,
         };
 
-        // Recreate and assing swapchain
-        self.swapchain = ManuallyDrop::new(swapchain);
+        // Pipelines are built for whatever render pass and samples are current, and
+        // only replaced if every one of them builds.
+        let old_render_pass = std::mem::replace(&mut self.render_pass, render_pass);
+        let old_samples = std::mem::replace(&mut self.samples, samples);
+        if let Err(e) = self.rebuild_pipelines() {
+            self.render_pass = old_render_pass;
+            self.samples = old_samples;
+            return Err(e);
+        }
+        if let Some((depth_buffer, color_buffer, framebuffers)) = attachments {
+            self.depth_buffer = Some(depth_buffer);
+            self.color_buffer = color_buffer;
+            self.framebuffers = framebuffers;
+        }
+        Ok(samples)
+    }
+
+    /// What frames are presented with, or `None` for a headless `HalState`.
+    pub fn present_config(&self) -> Option<PresentConfig> {
+        match self.target {
+            Target::Window(_) => Some(self.present),
+            Target::Offscreen(_) => None,
+        }
+    }
 
-        self.image_views = backbuffer
+    /// Rebuilds the swapchain to follow `present`, returning what it was made with, see
+    /// `presentation`. If that fails the config stays as it was.
+    pub fn set_present_config(
+        &mut self,
+        present: PresentConfig,
+    ) -> Result<Option<(PresentMode, usize)>, RendererError> {
+        let old = std::mem::replace(&mut self.present, present);
+        if let Err(e) = self.rebuild_swapchain() {
+            self.present = old;
+            return Err(e);
+        }
+        Ok(self.presentation())
+    }
+
+    /// The present mode and number of images of the swapchain, the closest to the
+    /// `PresentConfig` the surface supports. `None` without a swapchain: headless
+    /// `HalState`s have nothing to present, and a minimized window gets one once it's
+    /// restored.
+    pub fn presentation(&self) -> Option<(PresentMode, usize)> {
+        match &self.target {
+            Target::Window(window) if window.swapchain.is_some() => {
+                Some((window.present_mode, window.images.len()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The part of the frame that gets drawn to, in pixels. Sub-rectangles of this are
+    /// what `draw_triangles_frame` takes.
+    pub fn render_area(&self) -> Rect {
+        self.render_area
+    }
+
+    /// Rebuilds the pipelines meshes get by default with a different depth test, for
+    /// example `DepthTest::Off` for overlays that should always draw on top. Named
+    /// pipelines keep the depth test from their `PipelineDesc`.
+    pub fn set_depth_test(&mut self, depth_test: DepthTest) -> Result<(), RendererError> {
+        let replacements = self
+            .pipelines
+            .iter()
+            .enumerate()
+            .filter(|(_, pipeline)| pipeline.name.is_none())
+            .map(|(i, pipeline)| {
+                let desc = PipelineDesc {
+                    depth_test,
+                    ..pipeline.desc.clone()
+                };
+                (i, desc)
+            })
+            .collect();
+        self.replace_pipelines(replacements)?;
+        self.depth_test = depth_test;
+        Ok(())
+    }
+
+    pub fn depth_test(&self) -> DepthTest {
+        self.depth_test
+    }
+
+    /// Rebuilds every pipeline to draw with `view`'s shaders, or with their own again
+    /// for `DebugView::Off`. Pipelines created while it's on get it too.
+    pub fn set_debug_view(&mut self, view: DebugView) -> Result<(), RendererError> {
+        if view == self.debug_view {
+            return Ok(());
+        }
+        // Pipelines are built with whatever is set, so it's put back if any fail.
+        let previous = std::mem::replace(&mut self.debug_view, view);
+        if let Err(e) = self.rebuild_pipelines() {
+            self.debug_view = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    /// Rebuilds every pipeline to draw the edges of polygons rather than fill them,
+    /// or to fill them again. Needs `Features::NON_FILL_POLYGON_MODE`.
+    pub fn set_wireframe(&mut self, wireframe: bool) -> Result<(), RendererError> {
+        if wireframe == self.wireframe {
+            return Ok(());
+        }
+        self.wireframe = wireframe;
+        if let Err(e) = self.rebuild_pipelines() {
+            self.wireframe = !wireframe;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    /// The camera frames drawn from now on are seen through.
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+
+    /// How much GPU memory the renderer holds, and how much of it is in use.
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.allocator.stats()
+    }
+
+    /// Uploads a mesh into device local memory for `draw_meshes_frame`. The upload goes
+    /// out with the next frame, so the data can be dropped as soon as this returns.
+    /// Meshes with a vertex layout we haven't seen yet get a pipeline of their own.
+    pub fn create_mesh<V: Vertex, I: Index>(
+        &mut self,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<MeshHandle, RendererError> {
+        let pipeline = self.pipeline_for(V::layout())?;
+        self.create_mesh_in(pipeline, vertices, indices)
+    }
+
+    /// Like `create_mesh`, but the mesh is drawn with `pipeline`, which has to take
+    /// `V`s.
+    pub fn create_mesh_with_pipeline<V: Vertex, I: Index>(
+        &mut self,
+        pipeline: PipelineHandle,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<MeshHandle, RendererError> {
+        let desc = &self
+            .pipelines
+            .get(pipeline.0)
+            .ok_or(RendererError::InvalidHandle("pipeline"))?
+            .desc;
+        if desc.vertex_layout != V::layout() {
+            return Err(RendererError::InvalidArgument(
+                "The pipeline takes a different vertex type than the mesh is made of!",
+            ));
+        }
+        self.create_mesh_in(pipeline.0, vertices, indices)
+    }
+
+    fn create_mesh_in<V: Vertex, I: Index>(
+        &mut self,
+        pipeline: usize,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<MeshHandle, RendererError> {
+        let mesh = Mesh::new(
+            &self.device,
+            &mut self.allocator,
+            &mut self.uploader,
+            &mut self.queue_group.queues[0],
+            vertices,
+            indices,
+            pipeline,
+        )?;
+        Ok(MeshHandle(self.meshes.insert(mesh)))
+    }
+
+    /// Frees a mesh's buffers once the frames already drawn with it are done.
+    pub fn destroy_mesh(&mut self, mesh: MeshHandle) -> Result<(), RendererError> {
+        self.meshes
+            .remove(mesh.0)
+            .ok_or(RendererError::InvalidHandle("mesh"))?;
+        Ok(())
+    }
+
+    /// Submits every queued upload and blocks until the GPU has done them.
+    pub fn finish_uploads(&mut self) -> Result<(), RendererError> {
+        self.uploader
+            .finish(&self.device, &mut self.queue_group.queues[0])
+    }
+
+    /// Replaces the block textures with `layers`, one per block face, which all have to
+    /// be the same size. `BlockVertex::layer` indexes into them. This waits for the GPU
+    /// to go idle, so it's meant for load time.
+    pub fn set_block_textures(&mut self, layers: &[RgbaImage]) -> Result<(), RendererError> {
+        let textures = TextureArray::new(
+            &self.device,
+            &mut self.allocator,
+            &mut self.uploader,
+            &mut self.queue_group.queues[0],
+            layers,
+        )?;
+        // Every frame's descriptor set points at the old textures, so none of them can
+        // be in flight while it's rewritten.
+        self.device.wait_idle_and_collect(&mut self.allocator)?;
+        for frame in &self.frames {
+            unsafe { frame.write_block_textures(&self.device, &textures.view, &self.sampler) };
+        }
+        self.block_textures = textures;
+        Ok(())
+    }
+
+    /// How many layers the block textures have.
+    pub fn block_texture_layers(&self) -> u16 {
+        self.block_textures.layers
+    }
+
+    /// Loads each PNG in `paths` as a layer of the block textures, see
+    /// `set_block_textures`.
+    pub fn load_block_textures<P: AsRef<Path>>(
+        &mut self,
+        paths: &[P],
+    ) -> Result<(), RendererError> {
+        let layers = paths
+            .iter()
+            .map(RgbaImage::load_png)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.set_block_textures(&layers)
+    }
+
+    /// Builds a pipeline from `desc` and names it `name`. A pipeline that already has
+    /// the name is replaced, and meshes drawn with it use the new one from then on.
+    pub fn create_pipeline(
+        &mut self,
+        name: &str,
+        desc: PipelineDesc,
+    ) -> Result<PipelineHandle, RendererError> {
+        if let Some(handle) = self.pipeline(name) {
+            self.replace_pipelines(vec![(handle.0, desc)])?;
+            return Ok(handle);
+        }
+        let pipeline = self.build_pipeline(Some(name.to_string()), desc)?;
+        self.pipelines.push(pipeline);
+        Ok(PipelineHandle(self.pipelines.len() - 1))
+    }
+
+    /// The pipeline called `name`, if there is one.
+    pub fn pipeline(&self, name: &str) -> Option<PipelineHandle> {
+        self.pipelines
+            .iter()
+            .position(|pipeline| pipeline.name.as_deref() == Some(name))
+            .map(PipelineHandle)
+    }
+
+    /// The index of the default pipeline for `vertex_layout`, creating it if needed.
+    fn pipeline_for(&mut self, vertex_layout: VertexLayout) -> Result<usize, RendererError> {
+        if let Some(index) = self.pipelines.iter().position(|pipeline| {
+            pipeline.name.is_none() && pipeline.desc.vertex_layout == vertex_layout
+        }) {
+            return Ok(index);
+        }
+        let desc = PipelineDesc::for_vertex_layout(vertex_layout, self.depth_test);
+        let pipeline = self.build_pipeline(None, desc)?;
+        self.pipelines.push(pipeline);
+        Ok(self.pipelines.len() - 1)
+    }
+
+    /// Builds `desc` as the debug view and wireframe setting have it drawn. The
+    /// pipeline keeps `desc` itself, so it's built from that again once they change.
+    fn build_pipeline(
+        &mut self,
+        name: Option<String>,
+        desc: PipelineDesc,
+    ) -> Result<Pipeline, RendererError> {
+        let mut drawn = self.debug_view.apply(&desc);
+        if self.wireframe {
+            drawn.polygon_mode = PolygonMode::Line(1.0);
+        }
+        if drawn.polygon_mode != PolygonMode::Fill
+            && !self.features.contains(Features::NON_FILL_POLYGON_MODE)
+        {
+            return Err(RendererError::Unsupported(
+                "The device can only draw filled polygons!",
+            ));
+        }
+        if !self.reflected_shaders.contains_key(&drawn.shaders) {
+            let reflected = pipeline::reflect_shaders(&mut self.shader_compiler, drawn.shaders)?;
+            self.reflected_shaders.insert(drawn.shaders, reflected);
+        }
+        let mut pipeline = Pipeline::new(
+            &self.device,
+            &self.render_pass,
+            self.samples,
+            &self.descriptor_set_layouts,
+            &self.pipeline_cache,
+            &self.shader_interface,
+            &self.reflected_shaders[&drawn.shaders],
+            name,
+            drawn,
+        )?;
+        pipeline.desc = desc;
+        Ok(pipeline)
+    }
+
+    /// Builds each desc in place of the pipeline at its index, keeping the names.
+    /// Nothing is replaced unless every one of them builds.
+    fn replace_pipelines(
+        &mut self,
+        replacements: Vec<(usize, PipelineDesc)>,
+    ) -> Result<(), RendererError> {
+        let pipelines = replacements
             .into_iter()
-            .map(|image| unsafe {
-                self.device
-                    .create_image_view(
-                        &image,
-                        ViewKind::D2,
-                        format,
-                        Swizzle::NO,
-                        SubresourceRange {
-                            aspects: Aspects::COLOR,
-                            levels: 0..1,
-                            layers: 0..1,
-                        },
-                    )
-                    .map_err(|_| "Couldn't create the image_view for the image!")
+            .map(|(i, desc)| {
+                let pipeline = self.build_pipeline(self.pipelines[i].name.clone(), desc)?;
+                Ok((i, pipeline))
             })
-            .collect::<Result<Vec<_>, &str>>()
-            .unwrap();
+            .collect::<Result<Vec<_>, RendererError>>()?;
+        for (i, pipeline) in pipelines {
+            self.pipelines[i] = pipeline;
+        }
+        Ok(())
+    }
 
-        // Recreate framebuffers
-        self.framebuffers = {
-            self.image_views
+    /// Rebuilds the pipelines whose shaders, or anything they include, changed on disk
+    /// since the last call. A shader that doesn't compile is logged and its pipeline
+    /// is kept as it was. Cheap enough to call every frame.
+    #[cfg(feature = "runtime-shaders")]
+    pub fn reload_changed_shaders(&mut self) -> Result<(), RendererError> {
+        let changed = self.shader_watcher.changed();
+        self.reflected_shaders.retain(|_, shaders| {
+            !shaders
+                .vertex
+                .sources
                 .iter()
-                .map(|image_view| unsafe {
-                    self.device
-                        .create_framebuffer(
-                            &self.render_pass,
-                            vec![image_view],
-                            Extent {
-                                width: extent.width as u32,
-                                height: extent.height as u32,
-                                depth: 1,
-                            },
-                        )
-                        .map_err(|_| "Failed to create a framebuffer!")
-                })
-                .collect::<Result<Vec<_>, &str>>()
-                .unwrap()
+                .chain(&shaders.fragment.sources)
+                .any(|source| changed.contains(source))
+        });
+        let affected: Vec<usize> = (0..self.pipelines.len())
+            .filter(|&i| {
+                self.pipelines[i]
+                    .sources
+                    .iter()
+                    .any(|source| changed.contains(source))
+            })
+            .collect();
+        if affected.is_empty() {
+            return Ok(());
+        }
+        for i in affected {
+            let old = &self.pipelines[i];
+            match self.build_pipeline(old.name.clone(), old.desc.clone()) {
+                Ok(new) => {
+                    info!("Reloaded {}", new.desc.shaders);
+                    self.pipelines[i] = new;
+                }
+                Err(e) => error!("{}\nKeeping the last good pipeline.", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Clears the frame and draws every mesh in `meshes` over the whole render area,
+    /// each with its own push constants. Every mesh's pipeline has to take `P`s, which
+    /// is `DrawConstants` unless it was built to take something else.
+    /// `DebugView::Overdraw` clears to black instead, so only what's drawn adds up.
+    pub fn draw_meshes_frame<P: PushConstants>(
+        &mut self,
+        clear_color: [f32; 4],
+        meshes: &[(MeshHandle, P)],
+    ) -> Result<(), RendererError> {
+        // Checked up front, since bailing out once the frame has begun would leave its
+        // fence unsignalled.
+        for (handle, _) in meshes {
+            let mesh = self
+                .meshes
+                .get(handle.0)
+                .ok_or(RendererError::InvalidHandle("mesh"))?;
+            if self.pipelines[mesh.pipeline].desc.push_constants != P::range() {
+                return Err(RendererError::InvalidArgument(
+                    "A mesh's pipeline takes different push constants than it was given!",
+                ));
+            }
+        }
+        let clear_color = match self.debug_view {
+            DebugView::Overdraw => [0.0, 0.0, 0.0, 1.0],
+            _ => clear_color,
+        };
+        let (frame, image) = match self.begin_frame()? {
+            Some(acquired) => acquired,
+            None => return Ok(()),
         };
 
-        self.render_area = extent.to_extent().rect();
+        unsafe {
+            let context = &mut self.frames[frame];
+            let buffer = &mut context.command_buffer;
+            let clear_values = [
+                ClearValue::Color(ClearColor::Float(clear_color)),
+                ClearValue::DepthStencil(ClearDepthStencil(1.0, 0)),
+            ];
+            buffer.begin(false);
+            {
+                let mut encoder = buffer.begin_render_pass_inline(
+                    &self.render_pass,
+                    &self.framebuffers[image as usize],
+                    self.render_area,
+                    clear_values.iter(),
+                );
+                encoder.set_viewports(
+                    0,
+                    &[Viewport {
+                        rect: self.render_area,
+                        depth: 0.0..1.0,
+                    }],
+                );
+                encoder.set_scissors(0, &[self.render_area]);
+                let slots = &self.meshes;
+                let mut bound_pipeline = None;
+                let draws = meshes
+                    .iter()
+                    .filter_map(|(handle, constants)| Some((slots.get(handle.0)?, constants)));
+                for (mesh, constants) in draws {
+                    let pipeline = &self.pipelines[mesh.pipeline];
+                    if bound_pipeline != Some(mesh.pipeline) {
+                        encoder.bind_graphics_pipeline(&pipeline.raw);
+                        // Layouts with different push constants don't keep each other's
+                        // descriptor sets bound.
+                        encoder.bind_graphics_descriptor_sets(
+                            &pipeline.layout,
+                            0,
+                            Some(&context.descriptor_set),
+                            &[],
+                        );
+                        bound_pipeline = Some(mesh.pipeline);
+                    }
+                    let buffers: ArrayVec<[_; 1]> = [(&*mesh.vertex_buffer, 0)].into();
+                    encoder.bind_vertex_buffers(0, buffers);
+                    encoder.bind_index_buffer(gfx_hal::buffer::IndexBufferView {
+                        buffer: &mesh.index_buffer,
+                        offset: 0,
+                        index_type: mesh.index_type,
+                    });
+                    push::push_constants(&mut encoder, &pipeline.layout, constants);
+                    encoder.draw_indexed(0..mesh.index_count, 0, 0..1);
+                }
+            }
+            buffer.finish();
+        }
+
+        self.end_frame(frame, image)
+    }
+
+    /// Copies the last rendered frame back to the host as RGBA8. Only available on a
+    /// `HalState` made with `new_headless`, after at least one frame has been drawn.
+    pub fn read_pixels(&mut self) -> Result<RgbaImage, RendererError> {
+        if let Target::Window(_) = self.target {
+            return Err(RendererError::Unsupported(
+                "Only headless HalStates can read pixels back!",
+            ));
+        }
+        self.device.wait_idle()?;
+        self.read_image(0)
+    }
+
+    /// Writes the next frame drawn to `path` as a PNG.
+    pub fn capture_next_frame<P: Into<PathBuf>>(&mut self, path: P) -> Result<(), RendererError> {
+        self.check_capturable()?;
+        self.capture.request_frame(path.into());
+        Ok(())
+    }
+
+    /// Writes every frame drawn from now on into `dir` as numbered PNGs, until
+    /// `stop_capture_sequence` is called. Each frame waits on its copy, so expect the
+    /// frame rate to drop while this runs.
+    pub fn start_capture_sequence<P: Into<PathBuf>>(
+        &mut self,
+        dir: P,
+    ) -> Result<(), RendererError> {
+        self.check_capturable()?;
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        self.capture.start_sequence(dir);
+        Ok(())
+    }
+
+    /// Stops capturing frames, returning how many were written if a sequence was running.
+    pub fn stop_capture_sequence(&mut self) -> Option<u32> {
+        self.capture.stop_sequence()
+    }
+
+    pub fn is_capturing_sequence(&self) -> bool {
+        self.capture.is_sequence_running()
+    }
+
+    fn check_capturable(&self) -> Result<(), RendererError> {
+        match &self.target {
+            Target::Offscreen(_) => Ok(()),
+            Target::Window(window) if !window.usage.contains(Usage::TRANSFER_SRC) => Err(
+                RendererError::Unsupported("The swapchain images can't be copied from!"),
+            ),
+            Target::Window(window) if !capture::is_capturable(window.format) => Err(
+                RendererError::Unsupported("Can't capture frames in this swapchain format!"),
+            ),
+            Target::Window(_) => Ok(()),
+        }
+    }
+
+    /// Saves the just submitted `image` to every path waiting on a capture. Failures
+    /// are logged rather than returned, since the frame itself is fine, but they do
+    /// end a running sequence.
+    fn write_captures(&mut self, image: SwapImageIndex) {
+        let paths = self.capture.take_paths();
+        let written = self.read_image(image).and_then(|rgba| {
+            paths
+                .iter()
+                .try_for_each(|path| rgba.save_png(path).map(|_| info!("Captured {:?}", path)))
+        });
+        if let Err(e) = written {
+            error!("Couldn't capture frame: {}", e);
+            self.capture.stop_sequence();
+        }
+    }
+
+    /// Copies one of the target's images, as left by the render pass, back to the host
+    /// as RGBA8.
+    fn read_image(&mut self, index: SwapImageIndex) -> Result<RgbaImage, RendererError> {
+        let extent = Extent2D {
+            width: self.render_area.w as u32,
+            height: self.render_area.h as u32,
+        };
+        let pixels = unsafe {
+            readback::read_image(
+                &self.device,
+                &mut self.queue_group.queues[0],
+                &mut self.command_pool,
+                &mut self.allocator,
+                &self.target.images()[index as usize],
+                self.target.final_layout(),
+                extent,
+            )?
+        };
+        let pixels = capture::to_rgba(self.target.format(), pixels)?;
+        Ok(RgbaImage::new(extent.width, extent.height, pixels))
     }
 }
 
+/// The part of `rect` inside `area`, or `None` if they don't overlap.
+fn clip_rect(rect: Rect, area: Rect) -> Option<Rect> {
+    // Edges are worked out in `i32`, since they can be past what an `i16` holds.
+    let edges = |rect: Rect| {
+        let (x, y) = (i32::from(rect.x), i32::from(rect.y));
+        (x, y, x + i32::from(rect.w), y + i32::from(rect.h))
+    };
+    let (rect_left, rect_top, rect_right, rect_bottom) = edges(rect);
+    let (area_left, area_top, area_right, area_bottom) = edges(area);
+    let x = rect_left.max(area_left);
+    let y = rect_top.max(area_top);
+    let right = rect_right.min(area_right);
+    let bottom = rect_bottom.min(area_bottom);
+    if right <= x || bottom <= y {
+        return None;
+    }
+    // Inside `area`, so every one of these fits in an `i16` again.
+    let clamp = |value: i32| value.clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16;
+    Some(Rect {
+        x: clamp(x),
+        y: clamp(y),
+        w: clamp(right - x),
+        h: clamp(bottom - y),
+    })
+}
+
+/// Finds a memory type allowed by `type_mask` that has all of `properties`.
+pub(crate) fn find_memory_type(
+    memory_types: &[MemoryType],
+    type_mask: u64,
+    properties: gfx_hal::memory::Properties,
+) -> Option<MemoryTypeId> {
+    memory_types
+        .iter()
+        .enumerate()
+        .find(|&(id, memory_type)| {
+            type_mask & (1 << id) != 0 && memory_type.properties.contains(properties)
+        })
+        .map(|(id, _)| MemoryTypeId(id))
+}
+
 impl core::ops::Drop for HalState {
-    /// We have to clean up "leaf" elements before "root" elements. Basically, we
-    /// clean up in reverse of the order that we created things.
+    /// Once the GPU is idle nothing can be in use, so everything retired goes now and
+    /// the fields destroy themselves as they drop, in declaration order.
     fn drop(&mut self) {
-        self.device.wait_idle().unwrap();
-        unsafe {
-            for descriptor_set_layout in self.descriptor_set_layouts.drain(..) {
-                self.device
-                    .destroy_descriptor_set_layout(descriptor_set_layout)
-            }
-            for fence in self.in_flight_fences.drain(..) {
-                self.device.destroy_fence(fence)
-            }
-            for semaphore in self.render_finished_semaphores.drain(..) {
-                self.device.destroy_semaphore(semaphore)
-            }
-            for semaphore in self.image_available_semaphores.drain(..) {
-                self.device.destroy_semaphore(semaphore)
-            }
-            for framebuffer in self.framebuffers.drain(..) {
-                self.device.destroy_framebuffer(framebuffer);
-            }
-            for image_view in self.image_views.drain(..) {
-                self.device.destroy_image_view(image_view);
-            }
-            // LAST RESORT STYLE CODE, NOT TO BE IMITATED LIGHTLY
-            use core::ptr::read;
-            self.device
-                .destroy_buffer(ManuallyDrop::into_inner(read(&self.buffer)));
-            self.device
-                .free_memory(ManuallyDrop::into_inner(read(&self.memory)));
-            self.device
-                .destroy_pipeline_layout(ManuallyDrop::into_inner(read(&self.pipeline_layout)));
-            self.device
-                .destroy_graphics_pipeline(ManuallyDrop::into_inner(read(&self.graphics_pipeline)));
-            self.device.destroy_command_pool(
-                ManuallyDrop::into_inner(read(&self.command_pool)).into_raw(),
+        // A lost device is idle as far as anything still to be destroyed goes, and
+        // objects can still be destroyed on it.
+        if let Err(e) = self.device.wait_idle() {
+            error!(
+                "Couldn't wait for the device to go idle: {}",
+                RendererError::from(e)
             );
-            self.device
-                .destroy_render_pass(ManuallyDrop::into_inner(read(&self.render_pass)));
-            self.device
-                .destroy_swapchain(ManuallyDrop::into_inner(read(&self.swapchain)));
-            ManuallyDrop::drop(&mut self.device);
-            ManuallyDrop::drop(&mut self._instance);
         }
+        let saved = match unsafe { self.device.get_pipeline_cache_data(&self.pipeline_cache) } {
+            Ok(data) => {
+                pipeline_cache::save(Path::new(PIPELINE_CACHE_PATH), &self._adapter.info, &data)
+                    .map_err(RendererError::from)
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = saved {
+            warn!("Couldn't save the pipeline cache: {}", e);
+        }
+        self.device.tear_down(&mut self.allocator);
     }
 }
 
@@ -553,293 +1765,197 @@ impl Triangle {
 }
 
 impl HalState {
-    pub fn draw_triangle_frame(&mut self, triangle: Triangle) -> Result<(), &'static str> {
-        let image_available = &self.image_available_semaphores[self.current_frame];
-        let render_finished = &self.render_finished_semaphores[self.current_frame];
-        self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
-
-        let (i_u32, i_usize) = unsafe {
-            let check = self
-                .swapchain
-                .acquire_image(
-                    std::u64::MAX,
-                    Some(&self.image_available_semaphores[self.current_frame]),
-                    None,
-                )
-                .unwrap();
-            (check.0, check.0 as usize)
-        };
+    pub fn draw_triangle_frame(&mut self, triangle: Triangle) -> Result<(), RendererError> {
+        self.draw_triangles_frame(&[(self.render_area, triangle)])
+    }
 
-        let flight_fence = &self.in_flight_fences[i_usize];
-        unsafe {
-            self.device
-                .wait_for_fence(flight_fence, core::u64::MAX)
-                .map_err(|_| "Failed to wait on the fence!")?;
-            self.device
-                .reset_fence(flight_fence)
-                .map_err(|_| "Couldn't reset the fence!")?;
+    /// Draws each triangle into its own rectangle of the frame, with the triangle's
+    /// coordinates spanning that rectangle. Useful for split views and picture in
+    /// picture. Rectangles are clipped to `render_area`, and ones left empty are skipped.
+    pub fn draw_triangles_frame(
+        &mut self,
+        views: &[(Rect, Triangle)],
+    ) -> Result<(), RendererError> {
+        // The points are uploaded before an image is acquired, since bailing out after
+        // would leave the frame's fence unsignalled.
+        let frame = self.wait_for_frame()?;
+        let points: Vec<[f32; 6]> = views
+            .iter()
+            .map(|(_, triangle)| triangle.points_flat())
+            .collect();
+        // Never zero sized, even with nothing to draw.
+        let size = std::mem::size_of_val(&points[..]).max(1) as u64;
+        let upload = UploadBuffer::reserve(
+            &mut self.frames[frame].upload,
+            &self.device,
+            &mut self.allocator,
+            size,
+        )?;
+        if !points.is_empty() {
+            upload.write(&self.allocator, &points)?;
         }
+        let image = match self.acquire_frame(frame)? {
+            Some((_, image)) => image,
+            None => return Ok(()),
+        };
 
-        unsafe {
-            let mut data_target = self
-                .device
-                .acquire_mapping_writer(&self.memory, 0..self.requirements.size)
-                .map_err(|_| "Failed to acquire a memory writer!")?;
-            let points = triangle.points_flat();
-            data_target[..points.len()].copy_from_slice(&points);
-            self.device
-                .release_mapping_writer(data_target)
-                .map_err(|_| "Couldn't release the mapping writer!")?;
-        }
+        let context = &mut self.frames[frame];
+        let upload = &*context.upload.as_ref().unwrap().buffer;
 
         // RECORD COMMANDS
         unsafe {
-            let buffer = &mut self.command_buffers[i_usize];
-            const TRIANGLE_CLEAR: [ClearValue; 1] =
-                [ClearValue::Color(ClearColor::Float([0.1, 0.2, 0.3, 1.0]))];
+            let buffer = &mut context.command_buffer;
+            const TRIANGLE_CLEAR: [ClearValue; 2] = [
+                ClearValue::Color(ClearColor::Float([0.1, 0.2, 0.3, 1.0])),
+                ClearValue::DepthStencil(ClearDepthStencil(1.0, 0)),
+            ];
             buffer.begin(false);
             {
                 let mut encoder = buffer.begin_render_pass_inline(
                     &self.render_pass,
-                    &self.framebuffers[i_usize],
+                    &self.framebuffers[image as usize],
                     self.render_area,
                     TRIANGLE_CLEAR.iter(),
                 );
-                encoder.bind_graphics_pipeline(&self.graphics_pipeline);
-                // Here we must force the Deref impl of ManuallyDrop to play nice.
-                let buffer_ref: &<back::Backend as Backend>::Buffer = &self.buffer;
-                let buffers: ArrayVec<[_; 1]> = [(buffer_ref, 0)].into();
-                encoder.bind_vertex_buffers(0, buffers);
-                encoder.draw(0..3, 0..1);
+                let pipeline = &self.pipelines[0];
+                encoder.bind_graphics_pipeline(&pipeline.raw);
+                encoder.bind_graphics_descriptor_sets(
+                    &pipeline.layout,
+                    0,
+                    Some(&context.descriptor_set),
+                    &[],
+                );
+                push::push_constants(&mut encoder, &pipeline.layout, &DrawConstants::default());
+                let stride = std::mem::size_of::<[f32; 6]>() as u64;
+                for (i, (rect, _)) in views.iter().enumerate() {
+                    let rect = match clip_rect(*rect, self.render_area) {
+                        Some(rect) => rect,
+                        None => continue,
+                    };
+                    encoder.set_viewports(
+                        0,
+                        &[Viewport {
+                            rect,
+                            depth: 0.0..1.0,
+                        }],
+                    );
+                    encoder.set_scissors(0, &[rect]);
+                    let buffers: ArrayVec<[_; 1]> = [(upload, i as u64 * stride)].into();
+                    encoder.bind_vertex_buffers(0, buffers);
+                    encoder.draw(0..3, 0..1);
+                }
             }
             buffer.finish();
         }
 
-        let command_buffers = &self.command_buffers[i_usize..=i_usize];
-        let wait_semaphores: ArrayVec<[_; 1]> =
-            [(image_available, PipelineStage::COLOR_ATTACHMENT_OUTPUT)].into();
-        let signal_semaphores: ArrayVec<[_; 1]> = [render_finished].into();
-        let present_wait_semaphores: ArrayVec<[_; 1]> = [render_finished].into();
-        let submission = Submission {
-            command_buffers,
-            wait_semaphores,
-            signal_semaphores,
-        };
-        let the_command_queue = &mut self.queue_group.queues[0];
-        unsafe {
-            the_command_queue.submit(submission, Some(flight_fence));
-            self.swapchain
-                .present(the_command_queue, i_u32, present_wait_semaphores)
-                .map_err(|_| "Failed to present into the swapchain!")
-                .map(|_| ())
-        }
+        self.end_frame(frame, image)
     }
 
-    #[allow(clippy::type_complexity)]
-    fn create_pipeline(
-        device: &mut back::Device,
-        extent: Extent2D,
-        render_pass: &<back::Backend as Backend>::RenderPass,
-    ) -> Result<
-        (
-            Vec<<back::Backend as Backend>::DescriptorSetLayout>,
-            <back::Backend as Backend>::PipelineLayout,
-            <back::Backend as Backend>::GraphicsPipeline,
-        ),
-        &'static str,
-    > {
-        let mut compiler = shaderc::Compiler::new().ok_or("shaderc not found!")?;
-        let vertex_compile_artifact = compiler
-            .compile_into_spirv(
-                VERTEX_SOURCE,
-                shaderc::ShaderKind::Vertex,
-                "vertex.vert",
-                "main",
-                None,
-            )
-            .map_err(|_| "Couldn't compile vertex shader!")?;
-        let fragment_compile_artifact = compiler
-            .compile_into_spirv(
-                FRAGMENT_SOURCE,
-                shaderc::ShaderKind::Fragment,
-                "fragment.frag",
-                "main",
-                None,
-            )
-            .map_err(|e| {
-                error!("{}", e);
-                "Couldn't compile fragment shader!"
-            })?;
-        let vertex_shader_module = unsafe {
-            device
-                .create_shader_module(vertex_compile_artifact.as_binary_u8())
-                .map_err(|_| "Couldn't make the vertex module")?
-        };
-        let fragment_shader_module = unsafe {
-            device
-                .create_shader_module(fragment_compile_artifact.as_binary_u8())
-                .map_err(|_| "Couldn't make the fragment module")?
-        };
-        let (descriptor_set_layouts, pipeline_layout, gfx_pipeline) = {
-            let (vs_entry, fs_entry) = (
-                EntryPoint {
-                    entry: "main",
-                    module: &vertex_shader_module,
-                    specialization: Specialization {
-                        constants: std::borrow::Cow::Borrowed(&[]),
-                        data: std::borrow::Cow::Borrowed(&[]),
-                    },
-                },
-                EntryPoint {
-                    entry: "main",
-                    module: &fragment_shader_module,
-                    specialization: Specialization {
-                        constants: std::borrow::Cow::Borrowed(&[]),
-                        data: std::borrow::Cow::Borrowed(&[]),
-                    },
-                },
-            );
-
-            let shaders = GraphicsShaderSet {
-                vertex: vs_entry,
-                hull: None,
-                domain: None,
-                geometry: None,
-                fragment: Some(fs_entry),
-            };
-
-            let input_assembler = InputAssemblerDesc::new(Primitive::TriangleList);
-
-            let vertex_buffers: Vec<VertexBufferDesc> = vec![VertexBufferDesc {
-                binding: 0,
-                stride: (std::mem::size_of::<f32>() * 2) as u32,
-                rate: VertexInputRate::Instance(0),
-            }];
-            let attributes: Vec<AttributeDesc> = vec![AttributeDesc {
-                location: 0,
-                binding: 0,
-                element: Element {
-                    format: Format::Rg32Sfloat,
-                    offset: 0,
-                },
-            }];
-
-            // RASTERIZER
-            let rasterizer = Rasterizer {
-                depth_clamping: false,
-                polygon_mode: PolygonMode::Fill,
-                cull_face: Face::NONE,
-                front_face: FrontFace::Clockwise,
-                depth_bias: None,
-                conservative: false,
-            };
-
-            // DEPTH TESTING
-            let depth_stencil = DepthStencilDesc {
-                depth: DepthTest::Off,
-                depth_bounds: false,
-                stencil: StencilTest::Off,
-            };
-
-            // COLOR BLENDING
-            let blender = {
-                let blend_state = BlendState::On {
-                    color: BlendOp::Add {
-                        src: Factor::One,
-                        dst: Factor::Zero,
-                    },
-                    alpha: BlendOp::Add {
-                        src: Factor::One,
-                        dst: Factor::Zero,
-                    },
-                };
-                BlendDesc {
-                    logic_op: Some(LogicOp::Copy),
-                    targets: vec![ColorBlendDesc(ColorMask::ALL, blend_state)],
+    /// What the built in shaders take between them, checked against what the renderer
+    /// gives them: `FrameUniforms` at binding 0 and the block textures at binding 1.
+    /// Push constants are checked for each pipeline, against what it takes.
+    /// Each of them is returned too, compiled and reflected.
+    fn reflect_built_in_shaders(
+        compiler: &mut ShaderCompiler,
+    ) -> Result<(ShaderInterface, HashMap<ShaderSet, ReflectedShaders>), RendererError> {
+        let mut interface = ShaderInterface::default();
+        let mut reflected_shaders = HashMap::new();
+        for &shaders in BUILT_IN_SHADERS.iter() {
+            let reflected = pipeline::reflect_shaders(compiler, shaders)?;
+            interface.merge(&reflected.interface).map_err(|problem| {
+                RendererError::ShaderInterface {
+                    name: shaders.to_string(),
+                    problem,
                 }
-            };
-
-            // BAKED STATES
-            let baked_states = BakedStates {
-                viewport: Some(Viewport {
-                    rect: extent.to_extent().rect(),
-                    depth: (0.0..1.0),
-                }),
-                scissor: Some(extent.to_extent().rect()),
-                blend_color: None,
-                depth_bounds: None,
-            };
+            })?;
+            reflected_shaders.insert(shaders, reflected);
+        }
+        interface.inputs.clear();
+        interface.push_constants = None;
 
-            // NON BUFFER DATA SOURCES
-            let bindings = Vec::<DescriptorSetLayoutBinding>::new();
-            let immutable_samplers = Vec::<<back::Backend as Backend>::Sampler>::new();
-            let descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout> =
-                vec![unsafe {
-                    device
-                        .create_descriptor_set_layout(bindings, immutable_samplers)
-                        .map_err(|_| "Couldn't make a DescriptorSetLayout")?
-                }];
-            let push_constants = Vec::<(ShaderStageFlags, core::ops::Range<u32>)>::new();
-            let layout = unsafe {
-                device
-                    .create_pipeline_layout(&descriptor_set_layouts, push_constants)
-                    .map_err(|_| "Couldn't create a pipeline layout")?
-            };
+        let uniforms = interface.binding(0, DescriptorType::UniformBuffer);
+        let problem = if uniforms.is_none_or(|b| {
+            b.count != 1 || b.size > Some(std::mem::size_of::<FrameUniforms>() as u32)
+        }) {
+            Some("binding 0 has to be a uniform block no bigger than FrameUniforms".to_string())
+        } else if interface
+            .binding(1, DescriptorType::CombinedImageSampler)
+            .is_none_or(|b| b.count != 1)
+        {
+            Some("binding 1 has to be the block texture sampler".to_string())
+        } else {
+            None
+        };
+        match problem {
+            Some(problem) => Err(RendererError::ShaderInterface {
+                name: "The built in shaders".to_string(),
+                problem,
+            }),
+            None => Ok((interface, reflected_shaders)),
+        }
+    }
 
-            let gfx_pipeline = {
-                let desc = GraphicsPipelineDesc {
-                    shaders,
-                    rasterizer,
-                    vertex_buffers,
-                    attributes,
-                    input_assembler,
-                    blender,
-                    depth_stencil,
-                    multisampling: None,
-                    baked_states,
-                    layout: &layout,
-                    subpass: Subpass {
-                        index: 0,
-                        main_pass: render_pass,
-                    },
-                    flags: PipelineCreationFlags::empty(),
-                    parent: BasePipeline::None,
-                };
+    /// The descriptor set layouts every pipeline shares, made to fit `interface`.
+    fn create_descriptor_set_layouts(
+        device: &DeviceHandle,
+        interface: &ShaderInterface,
+    ) -> Result<Vec<resource::DescriptorSetLayout>, RendererError> {
+        // NON BUFFER DATA SOURCES
+        let bindings =
+            interface
+                .set_layout_bindings()
+                .map_err(|problem| RendererError::ShaderInterface {
+                    name: "The built in shaders".to_string(),
+                    problem,
+                })?;
+        let immutable_samplers = Vec::<<back::Backend as Backend>::Sampler>::new();
+        let descriptor_set_layout =
+            unsafe { device.create_descriptor_set_layout(bindings, immutable_samplers)? };
+        Ok(vec![resource::DescriptorSetLayout::new(
+            device,
+            descriptor_set_layout,
+        )])
+    }
+}
 
-                unsafe {
-                    device
-                        .create_graphics_pipeline(&desc, None)
-                        .map_err(|_| "Couldn't create a graphics pipeline!")?
-                }
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            (descriptor_set_layouts, layout, gfx_pipeline)
-        };
+    fn rect(x: i16, y: i16, w: i16, h: i16) -> Rect {
+        Rect { x, y, w, h }
+    }
 
-        unsafe {
-            device.destroy_shader_module(vertex_shader_module);
-            device.destroy_shader_module(fragment_shader_module);
-        }
+    #[test]
+    fn clip_rect_keeps_the_overlap() {
+        let area = rect(0, 0, 400, 300);
+        assert_eq!(
+            clip_rect(rect(300, 225, 200, 150), area),
+            Some(rect(300, 225, 100, 75))
+        );
+        assert_eq!(
+            clip_rect(rect(-50, -50, 100, 100), area),
+            Some(rect(0, 0, 50, 50))
+        );
+        assert_eq!(clip_rect(rect(400, 0, 10, 10), area), None);
+        assert_eq!(clip_rect(rect(10, 10, 0, 10), area), None);
+    }
 
-        Ok((descriptor_set_layouts, pipeline_layout, gfx_pipeline))
+    #[test]
+    fn clip_rect_doesnt_overflow() {
+        let area = rect(0, 0, 400, 300);
+        assert_eq!(clip_rect(rect(i16::MAX - 10, 0, i16::MAX, 10), area), None);
+        assert_eq!(
+            clip_rect(rect(100, 100, i16::MAX, i16::MAX), area),
+            Some(rect(100, 100, 300, 200))
+        );
+        assert_eq!(
+            clip_rect(rect(i16::MIN, i16::MIN, i16::MAX, i16::MAX), area),
+            None
+        );
+        assert_eq!(
+            clip_rect(rect(0, 0, 10, 10), rect(i16::MAX - 5, 0, i16::MAX, 10)),
+            None
+        );
     }
 }
-
-// VERTEX SHADER
-pub const VERTEX_SOURCE: &str = "#version 450
-layout (location = 0) in vec2 position;
-out gl_PerVertex {
-  vec4 gl_Position;
-};
-void main()
-{
-  gl_Position = vec4(position, 0.0, 1.0);
-}";
-
-// FRAGMENT SHADER
-pub const FRAGMENT_SOURCE: &str = "#version 450
-layout(location = 0) out vec4 color;
-void main()
-{
-  color = vec4(1.0);
-}";
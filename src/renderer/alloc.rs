@@ -0,0 +1,519 @@
+//! Sub-allocation of GPU memory.
+//!
+//! Drivers only allow a few thousand live `allocate_memory` calls, so resources get
+//! carved out of large blocks instead. Every memory type gets its own pools, with
+//! buffers and images kept in separate blocks so `buffer_image_granularity` never
+//! has to be accounted for.
+
+use super::{back, find_memory_type, resource::DeviceHandle, RendererError};
+
+use gfx_hal::{
+    adapter::MemoryType,
+    device::Device,
+    memory::{Properties, Requirements},
+    Backend, MemoryTypeId,
+};
+
+use std::{fmt, ops::Range};
+
+/// Size of the blocks pools are made of. Requests bigger than half of this get a
+/// block to themselves.
+pub const BLOCK_SIZE: u64 = 32 * 1024 * 1024;
+
+/// What the memory is going to be used for, which decides the memory type and the
+/// sub-allocation strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryUsage {
+    /// Only touched by the GPU. Meshes, textures, render targets.
+    DeviceLocal,
+    /// Mapped for the whole of its life and written by the CPU, like per frame data.
+    HostVisible,
+    /// Host visible, but only lives for a moment, like staging and readback buffers.
+    /// Allocated linearly, and a block only gets reused once everything in it is freed.
+    Transient,
+}
+
+impl MemoryUsage {
+    fn properties(self) -> Properties {
+        match self {
+            MemoryUsage::DeviceLocal => Properties::DEVICE_LOCAL,
+            MemoryUsage::HostVisible | MemoryUsage::Transient => {
+                Properties::CPU_VISIBLE | Properties::COHERENT
+            }
+        }
+    }
+}
+
+/// Whether the memory backs a buffer or an (optimally tiled) image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Buffer,
+    Image,
+}
+
+/// A piece of a block. Has to be given back with `Allocator::free`.
+#[derive(Debug)]
+pub struct Allocation {
+    pool: usize,
+    block: usize,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Hands out aligned ranges of a block.
+enum SubAllocator {
+    /// Bumps a cursor, and starts over once every allocation is freed.
+    Linear { cursor: u64, live: usize },
+    /// First fit over a sorted list of free ranges, merging neighbours on free.
+    FreeList { free: Vec<Range<u64>> },
+}
+
+impl SubAllocator {
+    fn new(usage: MemoryUsage, size: u64) -> Self {
+        match usage {
+            MemoryUsage::Transient => SubAllocator::Linear { cursor: 0, live: 0 },
+            _ => SubAllocator::FreeList {
+                free: std::iter::once(0..size).collect(),
+            },
+        }
+    }
+
+    fn alloc(&mut self, capacity: u64, size: u64, alignment: u64) -> Option<u64> {
+        match self {
+            SubAllocator::Linear { cursor, live } => {
+                let offset = align_up(*cursor, alignment);
+                if offset + size > capacity {
+                    return None;
+                }
+                *cursor = offset + size;
+                *live += 1;
+                Some(offset)
+            }
+            SubAllocator::FreeList { free } => {
+                let (i, offset) = free.iter().enumerate().find_map(|(i, range)| {
+                    let offset = align_up(range.start, alignment);
+                    if offset + size <= range.end {
+                        Some((i, offset))
+                    } else {
+                        None
+                    }
+                })?;
+                let range = free.remove(i);
+                // Whatever is left on either side stays free, padding included.
+                if offset + size < range.end {
+                    free.insert(i, offset + size..range.end);
+                }
+                if range.start < offset {
+                    free.insert(i, range.start..offset);
+                }
+                Some(offset)
+            }
+        }
+    }
+
+    fn free(&mut self, range: Range<u64>) {
+        match self {
+            SubAllocator::Linear { cursor, live } => {
+                *live -= 1;
+                if *live == 0 {
+                    *cursor = 0;
+                }
+            }
+            SubAllocator::FreeList { free } => {
+                let i = free
+                    .iter()
+                    .position(|free| free.start > range.start)
+                    .unwrap_or(free.len());
+                free.insert(i, range);
+                if i + 1 < free.len() && free[i].end == free[i + 1].start {
+                    free[i].end = free.remove(i + 1).end;
+                }
+                if i > 0 && free[i - 1].end == free[i].start {
+                    free[i - 1].end = free.remove(i).end;
+                }
+            }
+        }
+    }
+}
+
+struct Block {
+    memory: <back::Backend as Backend>::Memory,
+    size: u64,
+    /// Host visible blocks stay mapped for as long as they live.
+    mapped: Option<*mut u8>,
+    sub: SubAllocator,
+    used: u64,
+    allocations: usize,
+}
+
+struct Pool {
+    memory_type: MemoryTypeId,
+    usage: MemoryUsage,
+    kind: ResourceKind,
+    /// `None` where a block was freed, so block indices in allocations stay valid.
+    blocks: Vec<Option<Block>>,
+}
+
+impl Pool {
+    fn live_blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter().flatten()
+    }
+}
+
+/// Owns every block of GPU memory the renderer uses, and frees them all when dropped.
+/// Anything still bound to them must not be used after that.
+pub struct Allocator {
+    device: DeviceHandle,
+    memory_types: Vec<MemoryType>,
+    pools: Vec<Pool>,
+}
+
+impl Allocator {
+    pub fn new(device: DeviceHandle, memory_types: Vec<MemoryType>) -> Self {
+        Self {
+            device,
+            memory_types,
+            pools: Vec::new(),
+        }
+    }
+
+    /// Finds room for something with `requirements`, allocating a new block if needed.
+    pub fn allocate(
+        &mut self,
+        requirements: Requirements,
+        usage: MemoryUsage,
+        kind: ResourceKind,
+    ) -> Result<Allocation, RendererError> {
+        let memory_type = find_memory_type(
+            &self.memory_types,
+            requirements.type_mask,
+            usage.properties(),
+        )
+        .ok_or(RendererError::Setup(
+            "Couldn't find a memory type for the allocation!",
+        ))?;
+        let pool_index = match self.pools.iter().position(|pool| {
+            pool.memory_type == memory_type && pool.usage == usage && pool.kind == kind
+        }) {
+            Some(index) => index,
+            None => {
+                self.pools.push(Pool {
+                    memory_type,
+                    usage,
+                    kind,
+                    blocks: Vec::new(),
+                });
+                self.pools.len() - 1
+            }
+        };
+        let pool = &mut self.pools[pool_index];
+        let alignment = requirements.alignment.max(1);
+
+        if requirements.size <= BLOCK_SIZE / 2 {
+            for (block_index, block) in pool.blocks.iter_mut().enumerate() {
+                let block = match block {
+                    Some(block) => block,
+                    None => continue,
+                };
+                if let Some(offset) = block.sub.alloc(block.size, requirements.size, alignment) {
+                    block.used += requirements.size;
+                    block.allocations += 1;
+                    return Ok(Allocation {
+                        pool: pool_index,
+                        block: block_index,
+                        offset,
+                        size: requirements.size,
+                    });
+                }
+            }
+        }
+
+        // Nothing had room, so this gets a new block, sized to fit if it's a big one.
+        let size = requirements.size.max(BLOCK_SIZE);
+        let memory = unsafe { self.device.allocate_memory(memory_type, size)? };
+        let mapped = if usage == MemoryUsage::DeviceLocal {
+            None
+        } else {
+            match unsafe { self.device.map_memory(&memory, 0..size) } {
+                Ok(mapped) => Some(mapped),
+                Err(e) => {
+                    unsafe { self.device.free_memory(memory) };
+                    return Err(e.into());
+                }
+            }
+        };
+        let mut sub = SubAllocator::new(usage, size);
+        let offset = sub
+            .alloc(size, requirements.size, alignment)
+            .expect("A fresh block always fits its first allocation");
+        let block = Block {
+            memory,
+            size,
+            mapped,
+            sub,
+            used: requirements.size,
+            allocations: 1,
+        };
+        let block_index = match pool.blocks.iter().position(Option::is_none) {
+            Some(index) => {
+                pool.blocks[index] = Some(block);
+                index
+            }
+            None => {
+                pool.blocks.push(Some(block));
+                pool.blocks.len() - 1
+            }
+        };
+        Ok(Allocation {
+            pool: pool_index,
+            block: block_index,
+            offset,
+            size: requirements.size,
+        })
+    }
+
+    /// Gives the allocation's range back to its block. Empty blocks are released,
+    /// except for the last regular sized one in each pool, which is kept for reuse.
+    pub fn free(&mut self, allocation: Allocation) {
+        let pool = &mut self.pools[allocation.pool];
+        let block = pool.blocks[allocation.block]
+            .as_mut()
+            .expect("Allocation from a released block");
+        block
+            .sub
+            .free(allocation.offset..allocation.offset + allocation.size);
+        block.used -= allocation.size;
+        block.allocations -= 1;
+        if block.allocations == 0 && (block.size > BLOCK_SIZE || pool.live_blocks().count() > 1) {
+            let block = pool.blocks[allocation.block].take().unwrap();
+            unsafe { release(&self.device, block) };
+        }
+    }
+
+    /// The memory object an allocation lives in, for binding.
+    pub fn memory(&self, allocation: &Allocation) -> &<back::Backend as Backend>::Memory {
+        &self.block(allocation).memory
+    }
+
+    /// Copies `data` into a host visible allocation, `offset` bytes in.
+    pub fn write<T: Copy>(
+        &self,
+        allocation: &Allocation,
+        offset: u64,
+        data: &[T],
+    ) -> Result<(), RendererError> {
+        let size = std::mem::size_of_val(data) as u64;
+        let ptr = self.mapped(allocation, offset, size)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, ptr, size as usize);
+        }
+        Ok(())
+    }
+
+    /// Copies `len` bytes out of a host visible allocation.
+    pub fn read(&self, allocation: &Allocation, len: u64) -> Result<Vec<u8>, RendererError> {
+        let ptr = self.mapped(allocation, 0, len)?;
+        Ok(unsafe { std::slice::from_raw_parts(ptr, len as usize) }.to_vec())
+    }
+
+    fn mapped(
+        &self,
+        allocation: &Allocation,
+        offset: u64,
+        size: u64,
+    ) -> Result<*mut u8, RendererError> {
+        if offset + size > allocation.size {
+            return Err(RendererError::InvalidArgument(
+                "Access goes past the end of the allocation!",
+            ));
+        }
+        let base = self
+            .block(allocation)
+            .mapped
+            .ok_or(RendererError::InvalidArgument(
+                "Only host visible allocations can be accessed by the CPU!",
+            ))?;
+        Ok(unsafe { base.add((allocation.offset + offset) as usize) })
+    }
+
+    fn block(&self, allocation: &Allocation) -> &Block {
+        self.pools[allocation.pool].blocks[allocation.block]
+            .as_ref()
+            .expect("Allocation from a released block")
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            pools: self
+                .pools
+                .iter()
+                .map(|pool| PoolStats {
+                    memory_type: pool.memory_type.0,
+                    usage: pool.usage,
+                    kind: pool.kind,
+                    blocks: pool.live_blocks().count(),
+                    allocations: pool.live_blocks().map(|block| block.allocations).sum(),
+                    reserved: pool.live_blocks().map(|block| block.size).sum(),
+                    used: pool.live_blocks().map(|block| block.used).sum(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Drop for Allocator {
+    fn drop(&mut self) {
+        for pool in self.pools.drain(..) {
+            for block in pool.blocks.into_iter().flatten() {
+                unsafe { release(&self.device, block) };
+            }
+        }
+    }
+}
+
+unsafe fn release(device: &back::Device, block: Block) {
+    if block.mapped.is_some() {
+        device.unmap_memory(&block.memory);
+    }
+    device.free_memory(block.memory);
+}
+
+/// `value` rounded up to a multiple of `alignment`.
+pub(crate) fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+/// How much memory one pool holds and how much of it is in use.
+#[derive(Debug, Clone)]
+pub struct PoolStats {
+    pub memory_type: usize,
+    pub usage: MemoryUsage,
+    pub kind: ResourceKind,
+    pub blocks: usize,
+    pub allocations: usize,
+    /// Bytes allocated from the driver.
+    pub reserved: u64,
+    /// Bytes handed out to resources, not counting alignment padding.
+    pub used: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryStats {
+    pub pools: Vec<PoolStats>,
+}
+
+impl MemoryStats {
+    pub fn reserved(&self) -> u64 {
+        self.pools.iter().map(|pool| pool.reserved).sum()
+    }
+
+    pub fn used(&self) -> u64 {
+        self.pools.iter().map(|pool| pool.used).sum()
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        write!(
+            f,
+            "GPU memory: {:.1} of {:.1} MiB used",
+            self.used() as f64 / MIB,
+            self.reserved() as f64 / MIB
+        )?;
+        for pool in &self.pools {
+            write!(
+                f,
+                "\n  type {} {:?} {:?}: {} allocations in {} blocks, {:.1} of {:.1} MiB",
+                pool.memory_type,
+                pool.usage,
+                pool.kind,
+                pool.allocations,
+                pool.blocks,
+                pool.used as f64 / MIB,
+                pool.reserved as f64 / MIB
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_ranges(sub: &SubAllocator) -> &[Range<u64>] {
+        match sub {
+            SubAllocator::FreeList { free } => free,
+            SubAllocator::Linear { .. } => panic!("Not a free list"),
+        }
+    }
+
+    #[test]
+    fn allocations_are_aligned() {
+        let mut sub = SubAllocator::new(MemoryUsage::DeviceLocal, 1024);
+        assert_eq!(sub.alloc(1024, 10, 1), Some(0));
+        assert_eq!(sub.alloc(1024, 10, 256), Some(256));
+        // The padding before the aligned one stays free.
+        assert_eq!(free_ranges(&sub), &[10..256, 266..1024]);
+        assert_eq!(sub.alloc(1024, 100, 64), Some(64));
+
+        let mut linear = SubAllocator::new(MemoryUsage::Transient, 1024);
+        assert_eq!(linear.alloc(1024, 10, 1), Some(0));
+        assert_eq!(linear.alloc(1024, 10, 256), Some(256));
+    }
+
+    #[test]
+    fn freed_ranges_are_reused_first_fit() {
+        let mut sub = SubAllocator::new(MemoryUsage::DeviceLocal, 300);
+        let a = sub.alloc(300, 100, 1).unwrap();
+        let b = sub.alloc(300, 100, 1).unwrap();
+        sub.free(a..a + 100);
+        // The first free range that fits wins, even though the tail is bigger.
+        assert_eq!(sub.alloc(300, 50, 1), Some(0));
+        assert_eq!(sub.alloc(300, 60, 1), Some(200));
+        assert_eq!(b, 100);
+    }
+
+    #[test]
+    fn freeing_merges_with_both_neighbours() {
+        let mut sub = SubAllocator::new(MemoryUsage::DeviceLocal, 300);
+        let a = sub.alloc(300, 100, 1).unwrap();
+        let b = sub.alloc(300, 100, 1).unwrap();
+        let c = sub.alloc(300, 100, 1).unwrap();
+        assert!(free_ranges(&sub).is_empty());
+        sub.free(a..a + 100);
+        sub.free(c..c + 100);
+        assert_eq!(free_ranges(&sub), &[0..100, 200..300]);
+        sub.free(b..b + 100);
+        assert_eq!(free_ranges(&sub).len(), 1);
+        assert_eq!(free_ranges(&sub)[0], 0..300);
+        assert_eq!(sub.alloc(300, 300, 1), Some(0));
+    }
+
+    #[test]
+    fn full_blocks_refuse_allocations() {
+        let mut sub = SubAllocator::new(MemoryUsage::DeviceLocal, 256);
+        assert_eq!(sub.alloc(256, 200, 1), Some(0));
+        assert_eq!(sub.alloc(256, 100, 1), None);
+        // Fits in what's left, but not once aligned.
+        assert_eq!(sub.alloc(256, 50, 128), None);
+        assert_eq!(sub.alloc(256, 56, 1), Some(200));
+
+        let mut linear = SubAllocator::new(MemoryUsage::Transient, 256);
+        assert_eq!(linear.alloc(256, 200, 1), Some(0));
+        assert_eq!(linear.alloc(256, 100, 1), None);
+    }
+
+    #[test]
+    fn linear_blocks_start_over_once_empty() {
+        let mut linear = SubAllocator::new(MemoryUsage::Transient, 256);
+        let a = linear.alloc(256, 100, 1).unwrap();
+        let b = linear.alloc(256, 100, 1).unwrap();
+        linear.free(a..a + 100);
+        // Freeing only some of it gives nothing back.
+        assert_eq!(linear.alloc(256, 100, 1), None);
+        linear.free(b..b + 100);
+        assert_eq!(linear.alloc(256, 100, 1), Some(0));
+    }
+}
@@ -0,0 +1,89 @@
+/// A column major 4x4 matrix, the way GLSL reads a `mat4`.
+pub type Mat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Where the scene is looked at from. Clip space follows Vulkan: x right, y down,
+/// depth from 0 at the near plane to 1 at the far one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub view: Mat4,
+    pub projection: Mat4,
+}
+
+impl Default for Camera {
+    /// Leaves positions untouched, so geometry is given straight in clip space.
+    fn default() -> Self {
+        Self {
+            view: IDENTITY,
+            projection: IDENTITY,
+        }
+    }
+}
+
+impl Camera {
+    /// A right handed camera at `eye` looking at `target`.
+    pub fn look_at(eye: [f32; 3], target: [f32; 3], up: [f32; 3], projection: Mat4) -> Self {
+        let forward = normalize(sub(target, eye));
+        let right = normalize(cross(forward, up));
+        let up = cross(right, forward);
+        Self {
+            view: [
+                [right[0], up[0], -forward[0], 0.0],
+                [right[1], up[1], -forward[1], 0.0],
+                [right[2], up[2], -forward[2], 0.0],
+                [-dot(right, eye), -dot(up, eye), dot(forward, eye), 1.0],
+            ],
+            projection,
+        }
+    }
+}
+
+/// A perspective projection with a vertical field of view of `fov_y` radians.
+pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Mat4 {
+    let f = 1.0 / (fov_y / 2.0).tan();
+    [
+        [f / aspect, 0.0, 0.0, 0.0],
+        // Flipped, since Vulkan's y points down.
+        [0.0, -f, 0.0, 0.0],
+        [0.0, 0.0, far / (near - far), -1.0],
+        [0.0, 0.0, near * far / (near - far), 0.0],
+    ]
+}
+
+/// What the shaders see in the frame uniform buffer, laid out for std140.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FrameUniforms {
+    pub view: Mat4,
+    pub projection: Mat4,
+    /// Seconds since the `HalState` was created.
+    pub time: f32,
+    pub _padding: [f32; 3],
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = dot(a, a).sqrt();
+    [a[0] / length, a[1] / length, a[2] / length]
+}
@@ -0,0 +1,84 @@
+use super::RendererError;
+
+use arrayvec::ArrayVec;
+use gfx_hal::format::Format;
+
+use std::path::PathBuf;
+
+/// Frames waiting to be written out as PNGs once they've been rendered.
+#[derive(Debug, Default)]
+pub struct Capture {
+    next_frame: Option<PathBuf>,
+    sequence: Option<Sequence>,
+}
+
+/// Continuous capture, writing every frame into `dir` as `frame-00000.png`, `frame-00001.png`, ...
+#[derive(Debug)]
+struct Sequence {
+    dir: PathBuf,
+    next_index: u32,
+}
+
+impl Capture {
+    pub fn request_frame(&mut self, path: PathBuf) {
+        self.next_frame = Some(path);
+    }
+
+    pub fn start_sequence(&mut self, dir: PathBuf) {
+        self.sequence = Some(Sequence { dir, next_index: 0 });
+    }
+
+    /// Stops the running sequence, returning how many frames it wrote.
+    pub fn stop_sequence(&mut self) -> Option<u32> {
+        self.sequence.take().map(|sequence| sequence.next_index)
+    }
+
+    pub fn is_sequence_running(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.next_frame.is_some() || self.sequence.is_some()
+    }
+
+    /// Every path the frame being rendered right now should be written to.
+    pub fn take_paths(&mut self) -> ArrayVec<[PathBuf; 2]> {
+        let mut paths = ArrayVec::new();
+        if let Some(path) = self.next_frame.take() {
+            paths.push(path);
+        }
+        if let Some(sequence) = &mut self.sequence {
+            paths.push(
+                sequence
+                    .dir
+                    .join(format!("frame-{:05}.png", sequence.next_index)),
+            );
+            sequence.next_index += 1;
+        }
+        paths
+    }
+}
+
+/// Whether `to_rgba` knows how to turn images of this format into RGBA8.
+pub fn is_capturable(format: Format) -> bool {
+    matches!(
+        format,
+        Format::Rgba8Unorm | Format::Rgba8Srgb | Format::Bgra8Unorm | Format::Bgra8Srgb
+    )
+}
+
+/// Reorders tightly packed 8 bit texels of `format` into RGBA.
+pub fn to_rgba(format: Format, mut pixels: Vec<u8>) -> Result<Vec<u8>, RendererError> {
+    match format {
+        Format::Rgba8Unorm | Format::Rgba8Srgb => Ok(pixels),
+        Format::Bgra8Unorm | Format::Bgra8Srgb => {
+            for texel in pixels.chunks_mut(4) {
+                texel.swap(0, 2);
+            }
+            Ok(pixels)
+        }
+        _ => Err(RendererError::Unsupported(
+            "Can't capture frames in this swapchain format!",
+        )),
+    }
+}
@@ -0,0 +1,163 @@
+//! Which adapter a `HalState` runs on and what it asks of the device.
+
+use super::frame::DEFAULT_FRAMES_IN_FLIGHT;
+
+use gfx_hal::{
+    adapter::{AdapterInfo, DeviceType},
+    image::NumSamples,
+    window::PresentMode,
+    Features,
+};
+
+use std::{env, fmt};
+
+/// Set to an adapter's index or part of its name to run on that adapter.
+pub const ADAPTER_ENV_VAR: &str = "BLOXEL_ADAPTER";
+
+/// An adapter to use ahead of the rest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterChoice {
+    /// The adapter's place in the list logged at startup.
+    Index(usize),
+    /// Any adapter with this in its name, ignoring case.
+    Name(String),
+}
+
+impl AdapterChoice {
+    /// A number picks by index, anything else by name.
+    pub fn parse(choice: &str) -> Self {
+        match choice.trim().parse() {
+            Ok(index) => AdapterChoice::Index(index),
+            Err(_) => AdapterChoice::Name(choice.trim().to_string()),
+        }
+    }
+
+    fn matches(&self, index: usize, info: &AdapterInfo) -> bool {
+        match self {
+            AdapterChoice::Index(chosen) => *chosen == index,
+            AdapterChoice::Name(name) => info.name.to_lowercase().contains(&name.to_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for AdapterChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdapterChoice::Index(index) => write!(f, "adapter {}", index),
+            AdapterChoice::Name(name) => write!(f, "\"{}\"", name),
+        }
+    }
+}
+
+/// How frames are handed to the display. What the surface supports wins, so check
+/// `HalState::presentation` for what was applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresentConfig {
+    /// Never tear, by only swapping images on vertical blank.
+    pub vsync: bool,
+    /// Don't wait on the display to start the next frame. With vsync that means
+    /// newer frames replace queued ones, without it frames are shown right away.
+    pub uncapped: bool,
+    /// Three swapchain images rather than two, so the GPU never waits on one to be
+    /// shown.
+    pub triple_buffering: bool,
+}
+
+impl Default for PresentConfig {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            uncapped: true,
+            triple_buffering: true,
+        }
+    }
+}
+
+impl PresentConfig {
+    /// The present modes that fit, best first. Ends in `Fifo`, which every surface has.
+    pub fn present_modes(self) -> &'static [PresentMode] {
+        use gfx_hal::window::PresentMode::*;
+        match (self.vsync, self.uncapped) {
+            (true, true) => &[Mailbox, Fifo],
+            (true, false) => &[Fifo],
+            (false, true) => &[Immediate, Mailbox, Relaxed, Fifo],
+            // Vsynced, except frames that come too late are shown right away.
+            (false, false) => &[Relaxed, Immediate, Fifo],
+        }
+    }
+
+    /// How many swapchain images to ask for.
+    pub fn image_count(self) -> u32 {
+        if self.triple_buffering {
+            3
+        } else {
+            2
+        }
+    }
+}
+
+/// How a `HalState` is set up.
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// How many frames the CPU may record ahead of the GPU.
+    pub frames_in_flight: usize,
+    /// Used if it can draw to the target, otherwise the ranking decides.
+    pub adapter: Option<AdapterChoice>,
+    /// Ranks discrete GPUs ahead of integrated ones. Turn it off to save power.
+    pub prefer_discrete: bool,
+    /// Enabled where the adapter supports them, and done without otherwise.
+    pub features: Features,
+    /// Can be changed later with `HalState::set_present_config`.
+    pub present: PresentConfig,
+    /// Samples per pixel: 1 for no MSAA, or 2, 4 or 8. Lowered to what the device
+    /// supports, and can be changed later with `HalState::set_msaa`.
+    pub msaa: NumSamples,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            adapter: None,
+            prefer_discrete: true,
+            features: Features::NON_FILL_POLYGON_MODE | Features::SAMPLER_ANISOTROPY,
+            present: PresentConfig::default(),
+            msaa: 1,
+        }
+    }
+}
+
+impl RendererConfig {
+    /// The defaults, with the adapter picked by `ADAPTER_ENV_VAR` if it's set.
+    pub fn from_env() -> Self {
+        Self {
+            adapter: env::var(ADAPTER_ENV_VAR)
+                .ok()
+                .map(|choice| AdapterChoice::parse(&choice)),
+            ..Self::default()
+        }
+    }
+
+    /// Sorts adapters best first: the chosen one, then by device type, then in the
+    /// order they were enumerated.
+    pub fn adapter_rank(&self, index: usize, info: &AdapterInfo) -> (bool, u8, usize) {
+        let chosen = self.is_chosen(index, info);
+        let device_type = match info.device_type {
+            DeviceType::DiscreteGpu if self.prefer_discrete => 0,
+            DeviceType::IntegratedGpu if self.prefer_discrete => 1,
+            DeviceType::IntegratedGpu => 0,
+            DeviceType::DiscreteGpu => 1,
+            DeviceType::VirtualGpu => 2,
+            DeviceType::Other => 3,
+            DeviceType::Cpu => 4,
+        };
+        (!chosen, device_type, index)
+    }
+
+    /// Whether `adapter` names the adapter at `index`. Always true without a choice.
+    pub fn is_chosen(&self, index: usize, info: &AdapterInfo) -> bool {
+        self.adapter
+            .as_ref()
+            .is_none_or(|choice| choice.matches(index, info))
+    }
+}
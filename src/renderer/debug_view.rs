@@ -0,0 +1,84 @@
+//! Ways of drawing meshes that show how they were put together rather than how they
+//! should look, for checking what the mesher made.
+
+use super::{
+    mesh::{BlockVertex, Vertex},
+    pipeline::{BlendMode, PipelineDesc},
+    push::{DrawConstants, PushConstants},
+    shaders::{
+        DEBUG_CHUNK_SHADERS, DEBUG_NORMAL_SHADERS, DEBUG_OVERDRAW_SHADERS, DEBUG_UV_SHADERS,
+    },
+};
+
+use gfx_hal::pso::DepthTest;
+
+use std::fmt;
+
+/// What every pipeline draws with in place of its own shaders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugView {
+    /// Pipelines draw with their own shaders.
+    #[default]
+    Off,
+    /// Faces colored by the way they point, with each axis mapped to red, green and
+    /// blue from -1 to 1.
+    Normals,
+    /// Texture coordinates as red and green. Only meshes of `BlockVertex`es have them,
+    /// the rest keep their own shaders.
+    Uvs,
+    /// A color for each chunk, picked from its model matrix.
+    Chunks,
+    /// Brighter the more fragments land on a pixel, ignoring the depth test.
+    Overdraw,
+}
+
+impl fmt::Display for DebugView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DebugView::Off => "off",
+            DebugView::Normals => "normals",
+            DebugView::Uvs => "UVs",
+            DebugView::Chunks => "chunks",
+            DebugView::Overdraw => "overdraw",
+        })
+    }
+}
+
+impl DebugView {
+    /// The view after this one, going back to `Off` after the last.
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Off => DebugView::Normals,
+            DebugView::Normals => DebugView::Uvs,
+            DebugView::Uvs => DebugView::Chunks,
+            DebugView::Chunks => DebugView::Overdraw,
+            DebugView::Overdraw => DebugView::Off,
+        }
+    }
+
+    /// What a pipeline made from `desc` is built from while this view is on.
+    /// Pipelines that don't take `DrawConstants` keep their own shaders, since the
+    /// debug ones read those.
+    pub fn apply(self, desc: &PipelineDesc) -> PipelineDesc {
+        let mut desc = desc.clone();
+        if desc.push_constants != DrawConstants::range() {
+            return desc;
+        }
+        match self {
+            DebugView::Off => {}
+            DebugView::Normals => desc.shaders = DEBUG_NORMAL_SHADERS,
+            DebugView::Uvs => {
+                if desc.vertex_layout == BlockVertex::layout() {
+                    desc.shaders = DEBUG_UV_SHADERS;
+                }
+            }
+            DebugView::Chunks => desc.shaders = DEBUG_CHUNK_SHADERS,
+            DebugView::Overdraw => {
+                desc.shaders = DEBUG_OVERDRAW_SHADERS;
+                desc.depth_test = DepthTest::Off;
+                desc.blend = BlendMode::Additive;
+            }
+        }
+        desc
+    }
+}
@@ -0,0 +1,87 @@
+use super::{
+    alloc::Allocator,
+    back,
+    resource::{DeviceHandle, Image, ImageView},
+    RendererError,
+};
+
+use gfx_hal::{
+    adapter::PhysicalDevice,
+    device::Device,
+    format::{Aspects, Format, ImageFeature, Swizzle},
+    image::{Kind, NumSamples, SubresourceRange, Usage, ViewKind},
+    pso::{Comparison, DepthTest},
+    window::Extent2D,
+    Backend,
+};
+
+/// Depth formats we can render with, best first.
+const DEPTH_FORMATS: [Format; 3] = [
+    Format::D32Sfloat,
+    Format::D32SfloatS8Uint,
+    Format::D24UnormS8Uint,
+];
+
+/// Nearer fragments win, and they write their depth so later ones are tested against them.
+pub const DEPTH_TEST_ON: DepthTest = DepthTest::On {
+    fun: Comparison::LessEqual,
+    write: true,
+};
+
+/// Picks the first depth format the device can use as an optimally tiled attachment.
+pub fn pick_depth_format(
+    physical_device: &<back::Backend as Backend>::PhysicalDevice,
+) -> Result<Format, RendererError> {
+    DEPTH_FORMATS
+        .iter()
+        .cloned()
+        .find(|&format| {
+            physical_device
+                .format_properties(Some(format))
+                .optimal_tiling
+                .contains(ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .ok_or(RendererError::Setup("Couldn't find a usable depth format!"))
+}
+
+/// The depth image every framebuffer shares, sized like the color images.
+pub struct DepthBuffer {
+    pub view: ImageView,
+    _image: Image,
+}
+
+impl DepthBuffer {
+    pub fn new(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        extent: Extent2D,
+        format: Format,
+        samples: NumSamples,
+    ) -> Result<Self, RendererError> {
+        let image = Image::new(
+            device,
+            allocator,
+            Kind::D2(extent.width, extent.height, 1, samples),
+            1,
+            format,
+            Usage::DEPTH_STENCIL_ATTACHMENT,
+        )?;
+        let view = unsafe {
+            device.create_image_view(
+                &image,
+                ViewKind::D2,
+                format,
+                Swizzle::NO,
+                SubresourceRange {
+                    aspects: Aspects::DEPTH,
+                    levels: 0..1,
+                    layers: 0..1,
+                },
+            )?
+        };
+        Ok(Self {
+            view: ImageView::new(device, view),
+            _image: image,
+        })
+    }
+}
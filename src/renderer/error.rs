@@ -0,0 +1,296 @@
+use gfx_hal::{
+    buffer,
+    device::{
+        AllocationError, BindError, DeviceLost, OomOrDeviceLost, OutOfMemory, ShaderError,
+        SurfaceLost,
+    },
+    error::{DeviceCreationError, HostExecutionError},
+    image, mapping, pso,
+    window::{AcquireError, CreationError, PresentError},
+};
+
+use std::{error::Error, fmt, io};
+
+/// A gfx-hal error. Those only implement `failure::Fail`, so this is what lets
+/// `RendererError::source` hand them out as a `std::error::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HalError<E>(pub E);
+
+impl<E: fmt::Display> fmt::Display for HalError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<E: fmt::Display + fmt::Debug> Error for HalError<E> {}
+
+/// Everything that can go wrong inside the renderer.
+///
+/// The gfx-hal error that caused the failure is kept around so it can be logged,
+/// and `is_recoverable` tells the caller whether it's worth trying the next frame.
+#[derive(Debug)]
+pub enum RendererError {
+    /// Setup failed for a reason gfx-hal doesn't report on its own
+    /// (no adapter, no queue family, ...).
+    Setup(&'static str),
+    /// There's nothing to render with: no adapter can draw to the target, or the
+    /// graphics API or backend to find one with is missing.
+    NoAdapter(&'static str),
+    /// The caller passed something the renderer can't use, like an empty mesh.
+    /// Nothing was changed.
+    InvalidArgument(&'static str),
+    /// The device or target can't do what was asked, like drawing wireframes.
+    /// Nothing was changed.
+    Unsupported(&'static str),
+    DeviceCreation(HalError<DeviceCreationError>),
+    OutOfMemory(HalError<OutOfMemory>),
+    Allocation(HalError<AllocationError>),
+    Bind(HalError<BindError>),
+    BufferCreation(HalError<buffer::CreationError>),
+    ImageCreation(HalError<image::CreationError>),
+    ImageView(HalError<image::ViewError>),
+    Mapping(HalError<mapping::Error>),
+    DeviceLost(HalError<DeviceLost>),
+    HostExecution(HalError<HostExecutionError>),
+    SwapchainCreation(HalError<CreationError>),
+    /// The swapchain no longer matches the surface and has to be rebuilt.
+    SwapchainOutOfDate,
+    /// No swapchain image became available before the timeout.
+    SwapchainNotReady,
+    SurfaceLost(HalError<SurfaceLost>),
+    ShaderCompile {
+        name: String,
+        diagnostics: String,
+    },
+    /// The shaders don't fit together, or don't fit what the renderer gives them.
+    ShaderInterface {
+        name: String,
+        problem: String,
+    },
+    ShaderModule(HalError<ShaderError>),
+    PipelineCreation(HalError<pso::CreationError>),
+    DescriptorAllocation(HalError<pso::AllocationError>),
+    /// A handle to a resource that was already destroyed, naming the kind of resource.
+    InvalidHandle(&'static str),
+    Io(io::Error),
+    PngDecoding(png::DecodingError),
+    PngEncoding(png::EncodingError),
+}
+
+impl RendererError {
+    /// Recoverable errors only cost us the current frame or the call that failed,
+    /// everything else means the `HalState` should be torn down.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            RendererError::SwapchainOutOfDate
+                | RendererError::SwapchainNotReady
+                | RendererError::InvalidArgument(_)
+                | RendererError::Unsupported(_)
+        )
+    }
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RendererError::Setup(msg) => write!(f, "{}", msg),
+            RendererError::NoAdapter(msg) => write!(f, "{}", msg),
+            RendererError::InvalidArgument(msg) => write!(f, "{}", msg),
+            RendererError::Unsupported(msg) => write!(f, "{}", msg),
+            RendererError::DeviceCreation(e) => write!(f, "Couldn't open the device: {}", e),
+            RendererError::OutOfMemory(e) => write!(f, "{}", e),
+            RendererError::Allocation(e) => write!(f, "Allocation failed: {}", e),
+            RendererError::Bind(e) => write!(f, "Couldn't bind memory: {}", e),
+            RendererError::BufferCreation(e) => write!(f, "Couldn't create a buffer: {}", e),
+            RendererError::ImageCreation(e) => write!(f, "Couldn't create an image: {}", e),
+            RendererError::ImageView(e) => write!(f, "Couldn't create an image view: {}", e),
+            RendererError::Mapping(e) => write!(f, "Couldn't map memory: {}", e),
+            RendererError::DeviceLost(e) => write!(f, "{}", e),
+            RendererError::HostExecution(e) => write!(f, "{}", e),
+            RendererError::SwapchainCreation(e) => {
+                write!(f, "Couldn't create the swapchain: {}", e)
+            }
+            RendererError::SwapchainOutOfDate => write!(f, "Swapchain is out of date"),
+            RendererError::SwapchainNotReady => write!(f, "No swapchain image was ready"),
+            RendererError::SurfaceLost(e) => write!(f, "{}", e),
+            RendererError::ShaderCompile { name, diagnostics } => {
+                write!(f, "Couldn't compile {}:\n{}", name, diagnostics)
+            }
+            RendererError::ShaderInterface { name, problem } => {
+                write!(f, "{} doesn't fit: {}", name, problem)
+            }
+            RendererError::ShaderModule(e) => write!(f, "Couldn't make a shader module: {}", e),
+            RendererError::PipelineCreation(e) => write!(f, "Couldn't create a pipeline: {}", e),
+            RendererError::DescriptorAllocation(e) => {
+                write!(f, "Couldn't allocate a descriptor set: {}", e)
+            }
+            RendererError::InvalidHandle(kind) => {
+                write!(f, "The {} handle doesn't refer to a live {}", kind, kind)
+            }
+            RendererError::Io(e) => write!(f, "{}", e),
+            RendererError::PngDecoding(e) => write!(f, "Couldn't decode PNG: {}", e),
+            RendererError::PngEncoding(e) => write!(f, "Couldn't encode PNG: {}", e),
+        }
+    }
+}
+
+impl Error for RendererError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RendererError::DeviceCreation(e) => Some(e),
+            RendererError::OutOfMemory(e) => Some(e),
+            RendererError::Allocation(e) => Some(e),
+            RendererError::Bind(e) => Some(e),
+            RendererError::BufferCreation(e) => Some(e),
+            RendererError::ImageCreation(e) => Some(e),
+            RendererError::ImageView(e) => Some(e),
+            RendererError::Mapping(e) => Some(e),
+            RendererError::DeviceLost(e) => Some(e),
+            RendererError::HostExecution(e) => Some(e),
+            RendererError::SwapchainCreation(e) => Some(e),
+            RendererError::SurfaceLost(e) => Some(e),
+            RendererError::ShaderModule(e) => Some(e),
+            RendererError::PipelineCreation(e) => Some(e),
+            RendererError::DescriptorAllocation(e) => Some(e),
+            RendererError::Io(e) => Some(e),
+            RendererError::PngDecoding(e) => Some(e),
+            RendererError::PngEncoding(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DeviceCreationError> for RendererError {
+    fn from(e: DeviceCreationError) -> Self {
+        RendererError::DeviceCreation(HalError(e))
+    }
+}
+
+impl From<OutOfMemory> for RendererError {
+    fn from(e: OutOfMemory) -> Self {
+        RendererError::OutOfMemory(HalError(e))
+    }
+}
+
+impl From<OomOrDeviceLost> for RendererError {
+    fn from(e: OomOrDeviceLost) -> Self {
+        match e {
+            OomOrDeviceLost::OutOfMemory(e) => RendererError::OutOfMemory(HalError(e)),
+            OomOrDeviceLost::DeviceLost(e) => RendererError::DeviceLost(HalError(e)),
+        }
+    }
+}
+
+impl From<AllocationError> for RendererError {
+    fn from(e: AllocationError) -> Self {
+        RendererError::Allocation(HalError(e))
+    }
+}
+
+impl From<BindError> for RendererError {
+    fn from(e: BindError) -> Self {
+        RendererError::Bind(HalError(e))
+    }
+}
+
+impl From<buffer::CreationError> for RendererError {
+    fn from(e: buffer::CreationError) -> Self {
+        RendererError::BufferCreation(HalError(e))
+    }
+}
+
+impl From<image::CreationError> for RendererError {
+    fn from(e: image::CreationError) -> Self {
+        RendererError::ImageCreation(HalError(e))
+    }
+}
+
+impl From<image::ViewError> for RendererError {
+    fn from(e: image::ViewError) -> Self {
+        RendererError::ImageView(HalError(e))
+    }
+}
+
+impl From<mapping::Error> for RendererError {
+    fn from(e: mapping::Error) -> Self {
+        RendererError::Mapping(HalError(e))
+    }
+}
+
+impl From<DeviceLost> for RendererError {
+    fn from(e: DeviceLost) -> Self {
+        RendererError::DeviceLost(HalError(e))
+    }
+}
+
+impl From<HostExecutionError> for RendererError {
+    fn from(e: HostExecutionError) -> Self {
+        RendererError::HostExecution(HalError(e))
+    }
+}
+
+impl From<CreationError> for RendererError {
+    fn from(e: CreationError) -> Self {
+        RendererError::SwapchainCreation(HalError(e))
+    }
+}
+
+impl From<AcquireError> for RendererError {
+    fn from(e: AcquireError) -> Self {
+        match e {
+            AcquireError::OutOfMemory(e) => RendererError::OutOfMemory(HalError(e)),
+            AcquireError::NotReady => RendererError::SwapchainNotReady,
+            AcquireError::OutOfDate => RendererError::SwapchainOutOfDate,
+            AcquireError::SurfaceLost(e) => RendererError::SurfaceLost(HalError(e)),
+            AcquireError::DeviceLost(e) => RendererError::DeviceLost(HalError(e)),
+        }
+    }
+}
+
+impl From<PresentError> for RendererError {
+    fn from(e: PresentError) -> Self {
+        match e {
+            PresentError::OutOfMemory(e) => RendererError::OutOfMemory(HalError(e)),
+            PresentError::OutOfDate => RendererError::SwapchainOutOfDate,
+            PresentError::SurfaceLost(e) => RendererError::SurfaceLost(HalError(e)),
+            PresentError::DeviceLost(e) => RendererError::DeviceLost(HalError(e)),
+        }
+    }
+}
+
+impl From<ShaderError> for RendererError {
+    fn from(e: ShaderError) -> Self {
+        RendererError::ShaderModule(HalError(e))
+    }
+}
+
+impl From<pso::CreationError> for RendererError {
+    fn from(e: pso::CreationError) -> Self {
+        RendererError::PipelineCreation(HalError(e))
+    }
+}
+
+impl From<pso::AllocationError> for RendererError {
+    fn from(e: pso::AllocationError) -> Self {
+        RendererError::DescriptorAllocation(HalError(e))
+    }
+}
+
+impl From<io::Error> for RendererError {
+    fn from(e: io::Error) -> Self {
+        RendererError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for RendererError {
+    fn from(e: png::DecodingError) -> Self {
+        RendererError::PngDecoding(e)
+    }
+}
+
+impl From<png::EncodingError> for RendererError {
+    fn from(e: png::EncodingError) -> Self {
+        RendererError::PngEncoding(e)
+    }
+}
@@ -0,0 +1,143 @@
+use super::{
+    alloc::{Allocator, MemoryUsage},
+    back,
+    camera::FrameUniforms,
+    resource::{self, Buffer, DeviceHandle, Fence, Semaphore},
+    RendererError,
+};
+
+use gfx_hal::{
+    buffer,
+    command::*,
+    device::Device,
+    image::Layout,
+    pso::{Descriptor, DescriptorPool, DescriptorSetWrite},
+    Backend, Graphics,
+};
+
+/// How many frames the CPU may record ahead of the GPU unless asked otherwise.
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Everything one frame in flight touches. A frame context can only be reused once
+/// its fence has been signalled, so nothing in here is shared with another frame.
+/// The command buffer and descriptor set belong to their pools and go with them.
+pub struct FrameContext {
+    pub image_available: Semaphore,
+    pub render_finished: Semaphore,
+    pub fence: Fence,
+    /// The number `DeviceHandle::frame_submitted` gave this frame's last submission.
+    pub submitted: u64,
+    pub command_buffer: CommandBuffer<back::Backend, Graphics, MultiShot, Primary>,
+    /// Data streamed to the GPU this frame, grown on demand.
+    pub upload: Option<UploadBuffer>,
+    /// `FrameUniforms` for this frame, rewritten once its fence has been waited on.
+    pub uniforms: Buffer,
+    /// Set 0, pointing at `uniforms` and the block textures.
+    pub descriptor_set: <back::Backend as Backend>::DescriptorSet,
+}
+
+impl FrameContext {
+    pub fn new(
+        device: &DeviceHandle,
+        command_pool: &mut resource::CommandPool,
+        allocator: &mut Allocator,
+        descriptor_pool: &mut resource::DescriptorPool,
+        descriptor_set_layout: &<back::Backend as Backend>::DescriptorSetLayout,
+    ) -> Result<Self, RendererError> {
+        let uniforms = Buffer::new(
+            device,
+            allocator,
+            std::mem::size_of::<FrameUniforms>() as u64,
+            buffer::Usage::UNIFORM,
+            MemoryUsage::HostVisible,
+        )?;
+        let descriptor_set = unsafe {
+            let set = descriptor_pool.allocate_set(descriptor_set_layout)?;
+            device.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: &set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Buffer(&*uniforms, None..None)),
+            }));
+            set
+        };
+        Ok(Self {
+            image_available: Semaphore::new(device, device.create_semaphore()?),
+            render_finished: Semaphore::new(device, device.create_semaphore()?),
+            fence: Fence::new(device, device.create_fence(true)?),
+            submitted: 0,
+            command_buffer: command_pool.acquire_command_buffer(),
+            upload: None,
+            uniforms,
+            descriptor_set,
+        })
+    }
+
+    /// Points the descriptor set at a new block texture array. Only call this once the
+    /// frame's fence has been waited on.
+    pub unsafe fn write_block_textures(
+        &self,
+        device: &back::Device,
+        view: &<back::Backend as Backend>::ImageView,
+        sampler: &<back::Backend as Backend>::Sampler,
+    ) {
+        device.write_descriptor_sets(Some(DescriptorSetWrite {
+            set: &self.descriptor_set,
+            binding: 1,
+            array_offset: 0,
+            descriptors: Some(Descriptor::CombinedImageSampler(
+                view,
+                Layout::ShaderReadOnlyOptimal,
+                sampler,
+            )),
+        }));
+    }
+}
+
+/// A CPU visible vertex buffer that is rewritten every frame.
+pub struct UploadBuffer {
+    pub buffer: Buffer,
+    pub capacity: u64,
+}
+
+impl UploadBuffer {
+    /// Makes sure `slot` holds a buffer of at least `size` bytes and returns it.
+    /// Only call this once the owning frame's fence has been waited on.
+    pub fn reserve<'a>(
+        slot: &'a mut Option<UploadBuffer>,
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        size: u64,
+    ) -> Result<&'a UploadBuffer, RendererError> {
+        let too_small = match slot {
+            Some(upload) => upload.capacity < size,
+            None => true,
+        };
+        if too_small {
+            // Grow geometrically so a slowly growing stream doesn't reallocate every frame.
+            let capacity = size.next_power_of_two();
+            *slot = Some(UploadBuffer::new(device, allocator, capacity)?);
+        }
+        Ok(slot.as_ref().unwrap())
+    }
+
+    pub fn new(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        capacity: u64,
+    ) -> Result<Self, RendererError> {
+        let buffer = Buffer::new(
+            device,
+            allocator,
+            capacity,
+            buffer::Usage::VERTEX,
+            MemoryUsage::HostVisible,
+        )?;
+        Ok(Self { buffer, capacity })
+    }
+
+    /// Copies `data` to the start of the buffer.
+    pub fn write<T: Copy>(&self, allocator: &Allocator, data: &[T]) -> Result<(), RendererError> {
+        allocator.write(self.buffer.allocation(), 0, data)
+    }
+}
@@ -0,0 +1,162 @@
+use super::{
+    alloc::{Allocator, MemoryUsage},
+    back,
+    resource::{Buffer, DeviceHandle},
+    slots::SlotKey,
+    upload::Uploader,
+    RendererError,
+};
+
+use gfx_hal::{
+    buffer,
+    format::Format,
+    pso::{VertexBufferDesc, VertexInputRate},
+    queue::CommandQueue,
+    Graphics, IndexType,
+};
+
+/// How the vertices of a mesh are laid out in its vertex buffer.
+///
+/// Attribute `i` is read by the vertex shader from `location = i`, and has to be the
+/// same kind of number (float, signed or unsigned) the shader reads there. Meshes made of
+/// `BlockVertex`es get the textured block shaders. The plain built in shaders only read
+/// location 0 as the position, so any other attributes are along for the ride until a
+/// mesh gets its own shaders.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    /// Size of one vertex in bytes.
+    pub stride: u32,
+    /// Format and byte offset of every attribute, by location.
+    pub attributes: Vec<(Format, u32)>,
+}
+
+impl VertexLayout {
+    pub fn buffer_descs(&self) -> Vec<VertexBufferDesc> {
+        vec![VertexBufferDesc {
+            binding: 0,
+            stride: self.stride,
+            rate: VertexInputRate::Vertex,
+        }]
+    }
+}
+
+/// A vertex type meshes can be built from.
+pub trait Vertex: Copy {
+    fn layout() -> VertexLayout;
+}
+
+/// Bare 2D positions.
+impl Vertex for [f32; 2] {
+    fn layout() -> VertexLayout {
+        VertexLayout {
+            stride: 8,
+            attributes: vec![(Format::Rg32Sfloat, 0)],
+        }
+    }
+}
+
+/// Bare 3D positions.
+impl Vertex for [f32; 3] {
+    fn layout() -> VertexLayout {
+        VertexLayout {
+            stride: 12,
+            attributes: vec![(Format::Rgb32Sfloat, 0)],
+        }
+    }
+}
+
+/// A corner of a block face, textured from one layer of the block texture array.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockVertex {
+    pub position: [f32; 3],
+    /// Texture coordinates, where 0 to 1 spans the texture once.
+    pub uv: [f32; 2],
+    /// Which block texture the face uses.
+    pub layer: u32,
+}
+
+impl Vertex for BlockVertex {
+    fn layout() -> VertexLayout {
+        VertexLayout {
+            stride: 24,
+            attributes: vec![
+                (Format::Rgb32Sfloat, 0),
+                (Format::Rg32Sfloat, 12),
+                (Format::R32Uint, 20),
+            ],
+        }
+    }
+}
+
+/// The index types `draw_indexed` understands.
+pub trait Index: Copy {
+    const TYPE: IndexType;
+}
+
+impl Index for u16 {
+    const TYPE: IndexType = IndexType::U16;
+}
+
+impl Index for u32 {
+    const TYPE: IndexType = IndexType::U32;
+}
+
+/// Refers to a mesh owned by a `HalState`. Handles to destroyed meshes are rejected
+/// rather than drawing whatever got created after them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeshHandle(pub(crate) SlotKey);
+
+/// Vertex and index buffers ready to be drawn.
+pub struct Mesh {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub index_count: u32,
+    pub index_type: IndexType,
+    /// Which of the `HalState`'s pipelines matches this mesh's vertex layout.
+    pub pipeline: usize,
+}
+
+impl Mesh {
+    /// Creates the buffers in device local memory and queues uploads of the data.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<V: Vertex, I: Index>(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        uploader: &mut Uploader,
+        queue: &mut CommandQueue<back::Backend, Graphics>,
+        vertices: &[V],
+        indices: &[I],
+        pipeline: usize,
+    ) -> Result<Self, RendererError> {
+        if vertices.is_empty() || indices.is_empty() {
+            return Err(RendererError::InvalidArgument("Meshes can't be empty!"));
+        }
+        let mesh = Self {
+            vertex_buffer: Buffer::new(
+                device,
+                allocator,
+                std::mem::size_of_val(vertices) as u64,
+                buffer::Usage::VERTEX | buffer::Usage::TRANSFER_DST,
+                MemoryUsage::DeviceLocal,
+            )?,
+            index_buffer: Buffer::new(
+                device,
+                allocator,
+                std::mem::size_of_val(indices) as u64,
+                buffer::Usage::INDEX | buffer::Usage::TRANSFER_DST,
+                MemoryUsage::DeviceLocal,
+            )?,
+            index_count: indices.len() as u32,
+            index_type: I::TYPE,
+            pipeline,
+        };
+        // If this fails part way, a copy may already be recorded. That's fine, the
+        // buffers are only destroyed once the frame it goes out with is done.
+        unsafe {
+            uploader.upload_buffer(device, allocator, queue, vertices, &mesh.vertex_buffer, 0)?;
+            uploader.upload_buffer(device, allocator, queue, indices, &mesh.index_buffer, 0)?;
+        }
+        Ok(mesh)
+    }
+}
@@ -0,0 +1,92 @@
+//! Multisample anti-aliasing. With more than one sample per pixel the render pass
+//! draws into a multisampled color image and resolves it into the target's image at
+//! the end, so captures and presenting never see the samples.
+
+use super::{
+    alloc::Allocator,
+    resource::{DeviceHandle, Image, ImageView},
+    RendererError,
+};
+
+use gfx_hal::{
+    device::Device,
+    format::{Aspects, Format, Swizzle},
+    image::{Kind, NumSamples, SubresourceRange, Usage, ViewKind},
+    pso::Multisampling,
+    window::Extent2D,
+    Limits,
+};
+
+/// The sample counts the device can render color and depth with, as a mask with a
+/// bit set for each.
+pub fn supported_samples(limits: &Limits) -> NumSamples {
+    limits.framebuffer_color_samples_count & limits.framebuffer_depth_samples_count
+}
+
+/// The most samples up to `requested` that `supported` has, and never less than one.
+pub fn clamp_samples(requested: NumSamples, supported: NumSamples) -> NumSamples {
+    [8, 4, 2]
+        .iter()
+        .cloned()
+        .find(|&samples| samples <= requested && supported & samples != 0)
+        .unwrap_or(1)
+}
+
+/// What pipelines drawing with `samples` per pixel are built with.
+pub fn multisampling(samples: NumSamples) -> Option<Multisampling> {
+    if samples > 1 {
+        Some(Multisampling {
+            rasterization_samples: samples,
+            sample_shading: None,
+            sample_mask: !0,
+            alpha_coverage: false,
+            alpha_to_one: false,
+        })
+    } else {
+        None
+    }
+}
+
+/// The multisampled image every framebuffer draws into before resolving.
+pub struct ColorBuffer {
+    pub view: ImageView,
+    _image: Image,
+}
+
+impl ColorBuffer {
+    pub fn new(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        extent: Extent2D,
+        format: Format,
+        samples: NumSamples,
+    ) -> Result<Self, RendererError> {
+        // Only ever read by the resolve, so it never has to leave the tile memory of
+        // GPUs that have it.
+        let image = Image::new(
+            device,
+            allocator,
+            Kind::D2(extent.width, extent.height, 1, samples),
+            1,
+            format,
+            Usage::COLOR_ATTACHMENT | Usage::TRANSIENT_ATTACHMENT,
+        )?;
+        let view = unsafe {
+            device.create_image_view(
+                &image,
+                ViewKind::D2,
+                format,
+                Swizzle::NO,
+                SubresourceRange {
+                    aspects: Aspects::COLOR,
+                    levels: 0..1,
+                    layers: 0..1,
+                },
+            )?
+        };
+        Ok(Self {
+            view: ImageView::new(device, view),
+            _image: image,
+        })
+    }
+}
@@ -0,0 +1,361 @@
+//! Graphics pipelines, described by a `PipelineDesc` so any number of them can be
+//! built against the render pass, and built again when it or their shaders change.
+
+use super::{
+    back,
+    depth::DEPTH_TEST_ON,
+    mesh::{BlockVertex, Vertex, VertexLayout},
+    msaa,
+    push::{DrawConstants, PushConstants},
+    reflect::ShaderInterface,
+    resource::{DescriptorSetLayout, DeviceHandle, GraphicsPipeline, PipelineLayout, ShaderModule},
+    shaders::{
+        CompiledShader, ShaderCompiler, ShaderKind, ShaderSet, BLOCK_SHADERS, PLAIN_SHADERS,
+    },
+    RendererError,
+};
+
+use gfx_hal::{
+    device::Device,
+    image::NumSamples,
+    pass::Subpass,
+    pso::{
+        BakedStates, BasePipeline, BlendDesc, BlendOp, BlendState, ColorBlendDesc, ColorMask,
+        DepthStencilDesc, DepthTest, EntryPoint, Face, Factor, FrontFace, GraphicsPipelineDesc,
+        GraphicsShaderSet, InputAssemblerDesc, LogicOp, PipelineCreationFlags, PolygonMode,
+        Rasterizer, ShaderStageFlags, Specialization, StencilTest,
+    },
+    Backend, Primitive,
+};
+
+use std::ops::Range;
+#[cfg(feature = "runtime-shaders")]
+use std::path::PathBuf;
+
+/// How a pipeline's fragments are combined with what's already in the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Fragments replace what's there.
+    Opaque,
+    /// Fragments are mixed in by their alpha, for glass, water and overlays.
+    Alpha,
+    /// Fragments are added to what's there, for glows.
+    Additive,
+}
+
+impl BlendMode {
+    fn blend_desc(self) -> BlendDesc {
+        let (logic_op, state) = match self {
+            BlendMode::Opaque => (
+                Some(LogicOp::Copy),
+                BlendState::On {
+                    color: BlendOp::Add {
+                        src: Factor::One,
+                        dst: Factor::Zero,
+                    },
+                    alpha: BlendOp::Add {
+                        src: Factor::One,
+                        dst: Factor::Zero,
+                    },
+                },
+            ),
+            // A logic op turns blending off, so these can't have one.
+            BlendMode::Alpha => (None, BlendState::ALPHA),
+            BlendMode::Additive => (None, BlendState::ADD),
+        };
+        BlendDesc {
+            logic_op,
+            targets: vec![ColorBlendDesc(ColorMask::ALL, state)],
+        }
+    }
+}
+
+/// Everything a pipeline is built from, besides the render pass and pipeline layout
+/// every pipeline shares.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineDesc {
+    pub shaders: ShaderSet,
+    pub vertex_layout: VertexLayout,
+    pub primitive: Primitive,
+    pub cull_face: Face,
+    pub polygon_mode: PolygonMode,
+    pub depth_test: DepthTest,
+    pub blend: BlendMode,
+    /// What the push constants drawn with are, as `PushConstants::range` gives it.
+    pub push_constants: (ShaderStageFlags, Range<u32>),
+}
+
+impl PipelineDesc {
+    /// What meshes get when they're created without a pipeline: the block shaders for
+    /// `BlockVertex` and the plain ones for anything else, drawing opaque triangles.
+    pub fn for_vertex_layout(vertex_layout: VertexLayout, depth_test: DepthTest) -> Self {
+        let shaders = if vertex_layout == BlockVertex::layout() {
+            BLOCK_SHADERS
+        } else {
+            PLAIN_SHADERS
+        };
+        Self {
+            vertex_layout,
+            depth_test,
+            ..PipelineBuilder::new(shaders).build()
+        }
+    }
+}
+
+/// Builds a `PipelineDesc`. Unless told otherwise pipelines draw filled, opaque, depth
+/// tested triangle lists of `[f32; 3]` positions, without culling, and take
+/// `DrawConstants` for each draw.
+#[derive(Debug, Clone)]
+pub struct PipelineBuilder {
+    desc: PipelineDesc,
+}
+
+impl PipelineBuilder {
+    pub fn new(shaders: ShaderSet) -> Self {
+        Self {
+            desc: PipelineDesc {
+                shaders,
+                vertex_layout: <[f32; 3]>::layout(),
+                primitive: Primitive::TriangleList,
+                cull_face: Face::NONE,
+                polygon_mode: PolygonMode::Fill,
+                depth_test: DEPTH_TEST_ON,
+                blend: BlendMode::Opaque,
+                push_constants: DrawConstants::range(),
+            },
+        }
+    }
+
+    /// Meshes drawn with the pipeline have to be made of `V`s.
+    pub fn vertex<V: Vertex>(mut self) -> Self {
+        self.desc.vertex_layout = V::layout();
+        self
+    }
+
+    pub fn primitive(mut self, primitive: Primitive) -> Self {
+        self.desc.primitive = primitive;
+        self
+    }
+
+    /// Which faces aren't drawn. Clockwise faces are the front ones.
+    pub fn cull_face(mut self, cull_face: Face) -> Self {
+        self.desc.cull_face = cull_face;
+        self
+    }
+
+    /// Anything but `PolygonMode::Fill` needs `Features::NON_FILL_POLYGON_MODE`, which
+    /// `RendererConfig` asks for by default.
+    pub fn polygon_mode(mut self, polygon_mode: PolygonMode) -> Self {
+        self.desc.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn depth_test(mut self, depth_test: DepthTest) -> Self {
+        self.desc.depth_test = depth_test;
+        self
+    }
+
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.desc.blend = blend;
+        self
+    }
+
+    /// Meshes drawn with the pipeline are given a `P` for each draw. The shaders'
+    /// `push_constant` block has to fit in it.
+    pub fn push_constants<P: PushConstants>(mut self) -> Self {
+        self.desc.push_constants = P::range();
+        self
+    }
+
+    pub fn build(self) -> PipelineDesc {
+        self.desc
+    }
+}
+
+/// Refers to a named pipeline owned by a `HalState`. Pipelines live as long as the
+/// `HalState` does, so a handle stays good even if the pipeline is rebuilt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineHandle(pub(crate) usize);
+
+/// A graphics pipeline and what it was built from, so it can be built again.
+pub struct Pipeline {
+    /// `None` for the pipelines made for meshes created without one.
+    pub name: Option<String>,
+    pub desc: PipelineDesc,
+    /// Every file that went into the shaders.
+    #[cfg(feature = "runtime-shaders")]
+    pub sources: Vec<PathBuf>,
+    pub raw: GraphicsPipeline,
+    /// The shared descriptor set layouts, and the push constants of `desc`.
+    pub layout: PipelineLayout,
+}
+
+impl Pipeline {
+    /// Builds `desc` for the first subpass of `render_pass`, which draws with `samples`
+    /// per pixel, from `shaders`, which are `desc.shaders` already compiled. The shaders
+    /// have to fit `set_layouts`, made from `layout_interface`, and
+    /// `desc.push_constants`, and read the vertex attributes as the types
+    /// `desc.vertex_layout` gives.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &DeviceHandle,
+        render_pass: &<back::Backend as Backend>::RenderPass,
+        samples: NumSamples,
+        set_layouts: &[DescriptorSetLayout],
+        cache: &<back::Backend as Backend>::PipelineCache,
+        layout_interface: &ShaderInterface,
+        shaders: &ReflectedShaders,
+        name: Option<String>,
+        desc: PipelineDesc,
+    ) -> Result<Self, RendererError> {
+        let ReflectedShaders {
+            vertex,
+            fragment,
+            interface,
+        } = shaders;
+        let interface_error = |problem| RendererError::ShaderInterface {
+            name: desc.shaders.to_string(),
+            problem,
+        };
+        interface
+            .check_within(layout_interface)
+            .map_err(interface_error)?;
+        interface
+            .check_push_constants(&desc.push_constants)
+            .map_err(interface_error)?;
+        let attributes = interface
+            .vertex_attributes(&desc.vertex_layout)
+            .map_err(interface_error)?;
+        let layout = PipelineLayout::new(device, unsafe {
+            device.create_pipeline_layout(
+                set_layouts.iter().map(|layout| &**layout),
+                vec![desc.push_constants.clone()],
+            )?
+        });
+        let vertex_shader_module = ShaderModule::new(device, unsafe {
+            device.create_shader_module(&vertex.spirv)?
+        });
+        let fragment_shader_module = ShaderModule::new(device, unsafe {
+            device.create_shader_module(&fragment.spirv)?
+        });
+        let raw = {
+            let (vs_entry, fs_entry) = (
+                EntryPoint {
+                    entry: "main",
+                    module: &*vertex_shader_module,
+                    specialization: Specialization {
+                        constants: std::borrow::Cow::Borrowed(&[]),
+                        data: std::borrow::Cow::Borrowed(&[]),
+                    },
+                },
+                EntryPoint {
+                    entry: "main",
+                    module: &*fragment_shader_module,
+                    specialization: Specialization {
+                        constants: std::borrow::Cow::Borrowed(&[]),
+                        data: std::borrow::Cow::Borrowed(&[]),
+                    },
+                },
+            );
+
+            let shaders = GraphicsShaderSet {
+                vertex: vs_entry,
+                hull: None,
+                domain: None,
+                geometry: None,
+                fragment: Some(fs_entry),
+            };
+
+            let rasterizer = Rasterizer {
+                depth_clamping: false,
+                polygon_mode: desc.polygon_mode,
+                cull_face: desc.cull_face,
+                front_face: FrontFace::Clockwise,
+                depth_bias: None,
+                conservative: false,
+            };
+
+            let depth_stencil = DepthStencilDesc {
+                depth: desc.depth_test,
+                depth_bounds: false,
+                stencil: StencilTest::Off,
+            };
+
+            // Viewport and scissor are left dynamic, so resizes and sub-rectangle draws
+            // don't need a new pipeline.
+            let baked_states = BakedStates {
+                viewport: None,
+                scissor: None,
+                blend_color: None,
+                depth_bounds: None,
+            };
+
+            let pipeline_desc = GraphicsPipelineDesc {
+                shaders,
+                rasterizer,
+                vertex_buffers: desc.vertex_layout.buffer_descs(),
+                attributes,
+                input_assembler: InputAssemblerDesc::new(desc.primitive),
+                blender: desc.blend.blend_desc(),
+                depth_stencil,
+                multisampling: msaa::multisampling(samples),
+                baked_states,
+                layout: &*layout,
+                subpass: Subpass {
+                    index: 0,
+                    main_pass: render_pass,
+                },
+                flags: PipelineCreationFlags::empty(),
+                parent: BasePipeline::None,
+            };
+
+            unsafe { device.create_graphics_pipeline(&pipeline_desc, Some(cache)) }
+        };
+
+        Ok(Self {
+            name,
+            desc,
+            #[cfg(feature = "runtime-shaders")]
+            sources: vertex
+                .sources
+                .iter()
+                .chain(&fragment.sources)
+                .cloned()
+                .collect(),
+            raw: GraphicsPipeline::new(device, raw?),
+            layout,
+        })
+    }
+}
+
+/// A `ShaderSet` compiled, and what its shaders take between them.
+pub struct ReflectedShaders {
+    pub vertex: CompiledShader,
+    pub fragment: CompiledShader,
+    pub interface: ShaderInterface,
+}
+
+/// Compiles `shaders` and works out what they take together.
+pub fn reflect_shaders(
+    compiler: &mut ShaderCompiler,
+    shaders: ShaderSet,
+) -> Result<ReflectedShaders, RendererError> {
+    let vertex = compiler.compile(shaders.vertex, ShaderKind::Vertex)?;
+    let fragment = compiler.compile(shaders.fragment, ShaderKind::Fragment)?;
+    let interface_error = |name: &str| {
+        let name = name.to_string();
+        move |problem| RendererError::ShaderInterface { name, problem }
+    };
+    let mut interface = ShaderInterface::reflect(&vertex.spirv, ShaderStageFlags::VERTEX)
+        .map_err(interface_error(shaders.vertex))?;
+    let fragment_interface = ShaderInterface::reflect(&fragment.spirv, ShaderStageFlags::FRAGMENT)
+        .map_err(interface_error(shaders.fragment))?;
+    interface
+        .merge(&fragment_interface)
+        .map_err(interface_error(&shaders.to_string()))?;
+    Ok(ReflectedShaders {
+        vertex,
+        fragment,
+        interface,
+    })
+}
@@ -0,0 +1,61 @@
+//! Keeping the driver's pipeline cache between runs, so pipelines built last time
+//! come back quickly.
+//!
+//! The cache file starts with a header naming the adapter it was made on and a
+//! checksum of the data. A file from another adapter, or one that got cut short, is
+//! ignored and rebuilt. The driver's own data also carries its version, and the driver
+//! starts from empty if that doesn't match, so driver updates are safe too.
+
+use gfx_hal::adapter::AdapterInfo;
+
+use std::{fs, io, path::Path};
+
+/// Where the cache is kept between runs.
+pub const PIPELINE_CACHE_PATH: &str = "cache/pipelines.bin";
+
+/// Identifies the adapter and file format. Changing either invalidates old files.
+fn header(info: &AdapterInfo) -> Vec<u8> {
+    format!(
+        "bloxel pipeline cache 1\n{}\n{:04x}:{:04x}\n",
+        info.name, info.vendor, info.device
+    )
+    .into_bytes()
+}
+
+/// The cache data saved for this adapter, if there's any that's intact.
+pub fn load(path: &Path, info: &AdapterInfo) -> Option<Vec<u8>> {
+    let file = fs::read(path).ok()?;
+    let header = header(info);
+    if !file.starts_with(&header) || file.len() < header.len() + 8 {
+        return None;
+    }
+    let (checksum, data) = file[header.len()..].split_at(8);
+    let mut expected = [0; 8];
+    expected.copy_from_slice(checksum);
+    if u64::from_le_bytes(expected) == fnv1a(data) {
+        Some(data.to_vec())
+    } else {
+        None
+    }
+}
+
+/// Writes `data` to a temporary file first, so a crash part way through can't leave a
+/// broken cache behind.
+pub fn save(path: &Path, info: &AdapterInfo, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = header(info);
+    file.extend_from_slice(&fnv1a(data).to_le_bytes());
+    file.extend_from_slice(data);
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, file)?;
+    fs::rename(temporary, path)
+}
+
+/// 64 bit FNV-1a, plenty to catch a damaged file.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
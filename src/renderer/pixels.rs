@@ -0,0 +1,71 @@
+use super::RendererError;
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// An 8 bit per channel RGBA image in host memory, rows tightly packed, top row first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        debug_assert_eq!(pixels.len(), width as usize * height as usize * 4);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+
+    /// Loads any 8 or 16 bit PNG, converting it to RGBA8.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf)?;
+
+        let pixels = match info.color_type {
+            png::ColorType::RGBA => buf,
+            png::ColorType::RGB => buf
+                .chunks(3)
+                .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks(2)
+                .flat_map(|ga| vec![ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&g| vec![g, g, g, 255]).collect(),
+            // EXPAND turns palettes into RGB(A)
+            png::ColorType::Indexed => {
+                return Err(RendererError::InvalidArgument(
+                    "Indexed PNGs should have been expanded!",
+                ))
+            }
+        };
+        Ok(Self::new(info.width, info.height, pixels))
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), RendererError> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
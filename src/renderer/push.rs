@@ -0,0 +1,75 @@
+use super::{
+    back,
+    camera::{Mat4, IDENTITY},
+};
+
+use gfx_hal::{command::RenderPassInlineEncoder, pso::ShaderStageFlags, Backend};
+
+use core::ops::Range;
+
+/// Data pushed straight into the command buffer for each draw, rather than going
+/// through a uniform buffer. Pipelines take `DrawConstants` unless they're built with
+/// `PipelineBuilder::push_constants` for a type of their own.
+///
+/// # Safety
+///
+/// The value is handed to the GPU as raw words, so the type has to be `#[repr(C)]`, a
+/// multiple of 4 bytes in size, and laid out to match the `push_constant` block of the
+/// pipeline's shaders.
+pub unsafe trait PushConstants: Copy {
+    /// The shader stages that read the block.
+    const STAGES: ShaderStageFlags;
+
+    /// The stages and byte range the pipeline layout has to declare for this type.
+    fn range() -> (ShaderStageFlags, Range<u32>) {
+        (Self::STAGES, 0..std::mem::size_of::<Self>() as u32)
+    }
+
+    /// The value as the words `push_graphics_constants` takes.
+    fn as_words(&self) -> &[u32] {
+        debug_assert_eq!(std::mem::size_of::<Self>() % 4, 0);
+        unsafe {
+            std::slice::from_raw_parts(
+                self as *const Self as *const u32,
+                std::mem::size_of::<Self>() / 4,
+            )
+        }
+    }
+}
+
+/// What the built in shaders read per draw.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawConstants {
+    /// Takes the mesh's vertices into world space, before the camera sees them.
+    pub model: Mat4,
+}
+
+impl Default for DrawConstants {
+    fn default() -> Self {
+        Self { model: IDENTITY }
+    }
+}
+
+impl DrawConstants {
+    /// Moves the mesh by `offset`, like a chunk placed in the world.
+    pub fn translation(offset: [f32; 3]) -> Self {
+        let mut model = IDENTITY;
+        model[3] = [offset[0], offset[1], offset[2], 1.0];
+        Self { model }
+    }
+}
+
+unsafe impl PushConstants for DrawConstants {
+    const STAGES: ShaderStageFlags = ShaderStageFlags::VERTEX;
+}
+
+/// Sets `constants` for the draws that follow on `encoder`. The pipeline `layout` has to
+/// declare `P::range()`.
+pub unsafe fn push_constants<P: PushConstants>(
+    encoder: &mut RenderPassInlineEncoder<back::Backend>,
+    layout: &<back::Backend as Backend>::PipelineLayout,
+    constants: &P,
+) {
+    encoder.push_graphics_constants(layout, P::STAGES, 0, constants.as_words());
+}
@@ -0,0 +1,106 @@
+use super::{
+    alloc::{Allocator, MemoryUsage},
+    back,
+    resource::{Buffer, DeviceHandle, Fence},
+    RendererError,
+};
+
+use gfx_hal::{
+    buffer,
+    command::{BufferImageCopy, OneShot},
+    device::Device,
+    format::Aspects,
+    image::{Access, Extent, Layout, Offset, SubresourceLayers, SubresourceRange},
+    memory::{Barrier, Dependencies},
+    pool::CommandPool,
+    pso::PipelineStage,
+    queue::CommandQueue,
+    window::Extent2D,
+    Backend, Graphics,
+};
+
+/// Copies a whole 2D image with 4 bytes per texel into host memory, rows tightly packed.
+///
+/// The image has to be in `layout` and is put back into it afterwards. This blocks
+/// until the copy is done, so it's meant for screenshots and tests, not per frame use.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn read_image(
+    device: &DeviceHandle,
+    queue: &mut CommandQueue<back::Backend, Graphics>,
+    command_pool: &mut CommandPool<back::Backend, Graphics>,
+    allocator: &mut Allocator,
+    image: &<back::Backend as Backend>::Image,
+    layout: Layout,
+    extent: Extent2D,
+) -> Result<Vec<u8>, RendererError> {
+    let size = u64::from(extent.width) * u64::from(extent.height) * 4;
+    let buffer = Buffer::new(
+        device,
+        allocator,
+        size,
+        buffer::Usage::TRANSFER_DST,
+        MemoryUsage::Transient,
+    )?;
+
+    let range = SubresourceRange {
+        aspects: Aspects::COLOR,
+        levels: 0..1,
+        layers: 0..1,
+    };
+    let mut cmd = command_pool.acquire_command_buffer::<OneShot>();
+    cmd.begin();
+    // Whatever wrote the image last was a color attachment.
+    cmd.pipeline_barrier(
+        PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::TRANSFER,
+        Dependencies::empty(),
+        &[Barrier::Image {
+            states: (Access::COLOR_ATTACHMENT_WRITE, layout)
+                ..(Access::TRANSFER_READ, Layout::TransferSrcOptimal),
+            target: image,
+            families: None,
+            range: range.clone(),
+        }],
+    );
+    cmd.copy_image_to_buffer(
+        image,
+        Layout::TransferSrcOptimal,
+        &buffer,
+        &[BufferImageCopy {
+            buffer_offset: 0,
+            buffer_width: extent.width,
+            buffer_height: extent.height,
+            image_layers: SubresourceLayers {
+                aspects: Aspects::COLOR,
+                level: 0,
+                layers: 0..1,
+            },
+            image_offset: Offset { x: 0, y: 0, z: 0 },
+            image_extent: Extent {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        }],
+    );
+    if layout != Layout::TransferSrcOptimal {
+        cmd.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+            Dependencies::empty(),
+            &[Barrier::Image {
+                states: (Access::TRANSFER_READ, Layout::TransferSrcOptimal)
+                    ..(Access::empty(), layout),
+                target: image,
+                families: None,
+                range,
+            }],
+        );
+    }
+    cmd.finish();
+
+    let fence = Fence::new(device, device.create_fence(false)?);
+    queue.submit_nosemaphores(Some(&cmd), Some(&*fence));
+    let waited = device.wait_for_fence(&fence, u64::MAX);
+    command_pool.free(Some(cmd));
+    waited?;
+    allocator.read(buffer.allocation(), size)
+}
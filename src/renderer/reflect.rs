@@ -0,0 +1,930 @@
+//! Reading what a compiled shader expects out of its SPIR-V: vertex inputs, uniform
+//! blocks, images and samplers, and push constants. Layouts are built from this
+//! rather than written out by hand, so they can't drift from the GLSL.
+//!
+//! Only the handful of instructions that describe the interface are looked at, which
+//! is all the renderer needs.
+
+use super::mesh::VertexLayout;
+
+use gfx_hal::{
+    format::Format,
+    pso::{
+        AttributeDesc, DescriptorRangeDesc, DescriptorSetLayoutBinding, DescriptorType, Element,
+        ShaderStageFlags,
+    },
+};
+
+use std::{collections::HashMap, fmt, ops::Range};
+
+const MAGIC: u32 = 0x0723_0203;
+
+/// How deeply types can nest inside blocks. Far more than GLSL ever needs, but it stops
+/// a type that refers back to itself.
+const MAX_TYPE_DEPTH: u32 = 64;
+
+// Opcodes
+const OP_NAME: u32 = 5;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Decorations
+const BLOCK: u32 = 2;
+const BUFFER_BLOCK: u32 = 3;
+const ARRAY_STRIDE: u32 = 6;
+const MATRIX_STRIDE: u32 = 7;
+const BUILT_IN: u32 = 11;
+const LOCATION: u32 = 30;
+const BINDING: u32 = 33;
+const DESCRIPTOR_SET: u32 = 34;
+const OFFSET: u32 = 35;
+
+// Storage classes
+const UNIFORM_CONSTANT: u32 = 0;
+const INPUT: u32 = 1;
+const UNIFORM: u32 = 2;
+const PUSH_CONSTANT: u32 = 9;
+const STORAGE_BUFFER: u32 = 12;
+
+// Image dimensions
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// What a number read by a shader is made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Numeric {
+    Float,
+    Sint,
+    Uint,
+}
+
+/// A vertex shader input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub name: String,
+    pub numeric: Numeric,
+    pub components: u32,
+}
+
+/// A uniform block, image, sampler or buffer the shaders read through a descriptor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub set: u32,
+    pub binding: u32,
+    pub name: String,
+    pub ty: DescriptorType,
+    pub count: usize,
+    pub stages: ShaderStageFlags,
+    /// How many bytes of a uniform block the shaders read.
+    pub size: Option<u32>,
+}
+
+/// The `push_constant` block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PushConstantBlock {
+    pub stages: ShaderStageFlags,
+    pub size: u32,
+}
+
+/// Everything one or more shaders expect to be given.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShaderInterface {
+    /// Only vertex shaders have these.
+    pub inputs: Vec<VertexInput>,
+    pub bindings: Vec<Binding>,
+    pub push_constants: Option<PushConstantBlock>,
+}
+
+impl ShaderInterface {
+    /// Reflects a shader compiled for `stage`.
+    pub fn reflect(spirv: &[u8], stage: ShaderStageFlags) -> Result<Self, String> {
+        Module::parse(spirv)?.interface(stage)
+    }
+
+    /// Adds what `other` uses to this. The same binding has to mean the same thing
+    /// wherever it's used.
+    pub fn merge(&mut self, other: &Self) -> Result<(), String> {
+        if self.inputs.is_empty() {
+            self.inputs = other.inputs.clone();
+        }
+        for binding in &other.bindings {
+            match self
+                .bindings
+                .iter_mut()
+                .find(|b| b.set == binding.set && b.binding == binding.binding)
+            {
+                Some(existing) => {
+                    if existing.ty != binding.ty || existing.count != binding.count {
+                        return Err(format!(
+                            "set {} binding {} is a {} in one shader and a {} in another",
+                            binding.set,
+                            binding.binding,
+                            describe(existing.ty, existing.count),
+                            describe(binding.ty, binding.count),
+                        ));
+                    }
+                    existing.stages |= binding.stages;
+                    existing.size = existing.size.max(binding.size);
+                }
+                None => self.bindings.push(binding.clone()),
+            }
+        }
+        self.push_constants = match (self.push_constants, other.push_constants) {
+            (Some(a), Some(b)) => Some(PushConstantBlock {
+                stages: a.stages | b.stages,
+                size: a.size.max(b.size),
+            }),
+            (a, b) => a.or(b),
+        };
+        self.bindings.sort_by_key(|b| (b.set, b.binding));
+        Ok(())
+    }
+
+    /// Checks that everything this uses is declared by `layout`, the interface the
+    /// pipeline layout was made from.
+    pub fn check_within(&self, layout: &Self) -> Result<(), String> {
+        for binding in &self.bindings {
+            let declared = layout
+                .bindings
+                .iter()
+                .find(|b| b.set == binding.set && b.binding == binding.binding)
+                .ok_or_else(|| {
+                    format!(
+                        "`{}` at set {} binding {} isn't in the pipeline layout",
+                        binding.name, binding.set, binding.binding
+                    )
+                })?;
+            if declared.ty != binding.ty
+                || declared.count != binding.count
+                || !declared.stages.contains(binding.stages)
+                || declared.size < binding.size
+            {
+                return Err(format!(
+                    "`{}` at set {} binding {} doesn't match the pipeline layout, which has a {}",
+                    binding.name,
+                    binding.set,
+                    binding.binding,
+                    describe(declared.ty, declared.count)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the `push_constant` block, if there is one, is covered by `range`,
+    /// the push constants the pipeline is given.
+    pub fn check_push_constants(
+        &self,
+        range: &(ShaderStageFlags, Range<u32>),
+    ) -> Result<(), String> {
+        match self.push_constants {
+            Some(block) if !range.0.contains(block.stages) || block.size > range.1.end => {
+                Err(format!(
+                    "the {} byte push_constant block read in {:?} isn't covered by the {} bytes \
+                     given to {:?}",
+                    block.size, block.stages, range.1.end, range.0
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The binding at `binding` of set 0, if it is a `ty`.
+    pub fn binding(&self, binding: u32, ty: DescriptorType) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|b| b.set == 0 && b.binding == binding && b.ty == ty)
+    }
+
+    /// The bindings of set 0. Other sets aren't supported.
+    pub fn set_layout_bindings(&self) -> Result<Vec<DescriptorSetLayoutBinding>, String> {
+        self.bindings
+            .iter()
+            .map(|binding| {
+                if binding.set != 0 {
+                    return Err(format!(
+                        "`{}` is in descriptor set {}, but only set 0 is supported",
+                        binding.name, binding.set
+                    ));
+                }
+                Ok(DescriptorSetLayoutBinding {
+                    binding: binding.binding,
+                    ty: binding.ty,
+                    count: binding.count,
+                    stage_flags: binding.stages,
+                    immutable_samplers: false,
+                })
+            })
+            .collect()
+    }
+
+    /// What a descriptor pool needs to hold `sets` sets of these bindings.
+    pub fn descriptor_ranges(&self, sets: usize) -> Vec<DescriptorRangeDesc> {
+        let mut ranges: Vec<DescriptorRangeDesc> = Vec::new();
+        for binding in &self.bindings {
+            match ranges.iter_mut().find(|range| range.ty == binding.ty) {
+                Some(range) => range.count += binding.count * sets,
+                None => ranges.push(DescriptorRangeDesc {
+                    ty: binding.ty,
+                    count: binding.count * sets,
+                }),
+            }
+        }
+        ranges
+    }
+
+    /// An attribute for every input, taken from `layout` at the same location. Fails if
+    /// the vertex type is missing one, or gives it as the wrong kind or number of numbers.
+    /// A `vec4` can be given fewer, since the missing ones are filled in with 0 and a w
+    /// of 1, which is how positions are given as `[f32; 3]`.
+    pub fn vertex_attributes(&self, layout: &VertexLayout) -> Result<Vec<AttributeDesc>, String> {
+        self.inputs
+            .iter()
+            .map(|input| {
+                let &(format, offset) =
+                    layout
+                        .attributes
+                        .get(input.location as usize)
+                        .ok_or_else(|| {
+                            format!(
+                            "`{}` reads location {}, but the vertex type only has {} attributes",
+                            input.name,
+                            input.location,
+                            layout.attributes.len()
+                        )
+                        })?;
+                match format_input(format) {
+                    Some((numeric, components))
+                        if numeric == input.numeric
+                            && (components == input.components
+                                || input.components == 4 && components < 4) =>
+                    {
+                        Ok(AttributeDesc {
+                            location: input.location,
+                            binding: 0,
+                            element: Element { format, offset },
+                        })
+                    }
+                    Some((numeric, components)) => Err(format!(
+                        "`{}` at location {} is read as {} {:?} but the vertex type gives {} \
+                         {:?} ({:?})",
+                        input.name,
+                        input.location,
+                        input.components,
+                        input.numeric,
+                        components,
+                        numeric,
+                        format
+                    )),
+                    None => Err(format!(
+                        "location {} of the vertex type is {:?}, which can't be a vertex input",
+                        input.location, format
+                    )),
+                }
+            })
+            .collect()
+    }
+}
+
+fn describe(ty: DescriptorType, count: usize) -> String {
+    if count == 1 {
+        format!("{:?}", ty)
+    } else {
+        format!("{:?}[{}]", ty, count)
+    }
+}
+
+/// What the shader sees when it reads `format` as a vertex attribute, and how many
+/// components.
+fn format_input(format: Format) -> Option<(Numeric, u32)> {
+    use self::Format::*;
+    let numeric = match format {
+        R32Sfloat | Rg32Sfloat | Rgb32Sfloat | Rgba32Sfloat | R16Sfloat | Rg16Sfloat
+        | Rgba16Sfloat | R16Unorm | Rg16Unorm | Rgba16Unorm | R16Snorm | Rg16Snorm
+        | Rgba16Snorm | R8Unorm | Rg8Unorm | Rgba8Unorm | R8Snorm | Rg8Snorm | Rgba8Snorm => {
+            Numeric::Float
+        }
+        R32Uint | Rg32Uint | Rgb32Uint | Rgba32Uint | R16Uint | Rg16Uint | Rgba16Uint | R8Uint
+        | Rg8Uint | Rgba8Uint => Numeric::Uint,
+        R32Sint | Rg32Sint | Rgb32Sint | Rgba32Sint | R16Sint | Rg16Sint | Rgba16Sint | R8Sint
+        | Rg8Sint | Rgba8Sint => Numeric::Sint,
+        _ => return None,
+    };
+    let components = match format {
+        R32Sfloat | R16Sfloat | R16Unorm | R16Snorm | R8Unorm | R8Snorm | R32Uint | R16Uint
+        | R8Uint | R32Sint | R16Sint | R8Sint => 1,
+        Rg32Sfloat | Rg16Sfloat | Rg16Unorm | Rg16Snorm | Rg8Unorm | Rg8Snorm | Rg32Uint
+        | Rg16Uint | Rg8Uint | Rg32Sint | Rg16Sint | Rg8Sint => 2,
+        Rgb32Sfloat | Rgb32Uint | Rgb32Sint => 3,
+        _ => 4,
+    };
+    Some((numeric, components))
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage { image: u32 },
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Debug, Clone, Default)]
+struct Decorations {
+    location: Option<u32>,
+    binding: Option<u32>,
+    set: Option<u32>,
+    built_in: bool,
+    block: bool,
+    buffer_block: bool,
+    array_stride: Option<u32>,
+}
+
+/// The parts of a SPIR-V module that describe its interface.
+#[derive(Default)]
+struct Module {
+    names: HashMap<u32, String>,
+    decorations: HashMap<u32, Decorations>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    matrix_strides: HashMap<(u32, u32), u32>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Id, type and storage class.
+    variables: Vec<(u32, u32, u32)>,
+}
+
+struct Malformed;
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the SPIR-V is malformed")
+    }
+}
+
+impl From<Malformed> for String {
+    fn from(malformed: Malformed) -> Self {
+        malformed.to_string()
+    }
+}
+
+impl Module {
+    fn parse(bytes: &[u8]) -> Result<Self, Malformed> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(Malformed);
+        }
+        let words: Vec<u32> = bytes
+            .chunks(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        if words.len() < 5 || words[0] != MAGIC {
+            return Err(Malformed);
+        }
+
+        let mut module = Module::default();
+        let mut rest = &words[5..];
+        while !rest.is_empty() {
+            let count = (rest[0] >> 16) as usize;
+            if count == 0 || count > rest.len() {
+                return Err(Malformed);
+            }
+            let (instruction, next) = rest.split_at(count);
+            module.read(instruction[0] & 0xffff, &instruction[1..])?;
+            rest = next;
+        }
+        Ok(module)
+    }
+
+    /// Takes note of one instruction, if it's one we care about.
+    fn read(&mut self, opcode: u32, operands: &[u32]) -> Result<(), Malformed> {
+        let operand = |i: usize| operands.get(i).cloned().ok_or(Malformed);
+        match opcode {
+            OP_NAME => {
+                let id = operand(0)?;
+                self.names.insert(id, string(&operands[1..]));
+            }
+            OP_DECORATE => {
+                let decorations = self.decorations.entry(operand(0)?).or_default();
+                match operand(1)? {
+                    LOCATION => decorations.location = Some(operand(2)?),
+                    BINDING => decorations.binding = Some(operand(2)?),
+                    DESCRIPTOR_SET => decorations.set = Some(operand(2)?),
+                    BUILT_IN => decorations.built_in = true,
+                    BLOCK => decorations.block = true,
+                    BUFFER_BLOCK => decorations.buffer_block = true,
+                    ARRAY_STRIDE => decorations.array_stride = Some(operand(2)?),
+                    _ => {}
+                }
+            }
+            OP_MEMBER_DECORATE => {
+                let member = (operand(0)?, operand(1)?);
+                match operand(2)? {
+                    OFFSET => {
+                        self.member_offsets.insert(member, operand(3)?);
+                    }
+                    MATRIX_STRIDE => {
+                        self.matrix_strides.insert(member, operand(3)?);
+                    }
+                    _ => {}
+                }
+            }
+            OP_TYPE_BOOL => {
+                self.types.insert(operand(0)?, Type::Bool);
+            }
+            OP_TYPE_INT => {
+                let ty = Type::Int {
+                    width: operand(1)?,
+                    signed: operand(2)? != 0,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_FLOAT => {
+                let ty = Type::Float { width: operand(1)? };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_VECTOR => {
+                let ty = Type::Vector {
+                    component: operand(1)?,
+                    count: operand(2)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_MATRIX => {
+                let ty = Type::Matrix {
+                    column: operand(1)?,
+                    count: operand(2)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_IMAGE => {
+                let ty = Type::Image {
+                    dim: operand(2)?,
+                    sampled: operand(6)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_SAMPLER => {
+                self.types.insert(operand(0)?, Type::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                let ty = Type::SampledImage { image: operand(1)? };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_ARRAY => {
+                let ty = Type::Array {
+                    element: operand(1)?,
+                    length: operand(2)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                self.types.insert(operand(0)?, Type::RuntimeArray);
+            }
+            OP_TYPE_STRUCT => {
+                let id = operand(0)?;
+                let ty = Type::Struct {
+                    members: operands[1..].to_vec(),
+                };
+                self.types.insert(id, ty);
+            }
+            OP_TYPE_POINTER => {
+                let ty = Type::Pointer {
+                    pointee: operand(2)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_CONSTANT => {
+                self.constants.insert(operand(1)?, operand(2)?);
+            }
+            OP_VARIABLE => {
+                self.variables.push((operand(1)?, operand(0)?, operand(2)?));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn interface(&self, stage: ShaderStageFlags) -> Result<ShaderInterface, String> {
+        let mut interface = ShaderInterface::default();
+        for &(id, pointer, storage) in &self.variables {
+            let ty = match self.types.get(&pointer) {
+                Some(Type::Pointer { pointee }) => *pointee,
+                _ => return Err(Malformed.into()),
+            };
+            let decorations = self.decorations.get(&id).cloned().unwrap_or_default();
+            match storage {
+                INPUT if stage == ShaderStageFlags::VERTEX && !decorations.built_in => {
+                    let location = decorations.location.ok_or_else(|| {
+                        format!("the input `{}` has no location", self.name(id, ty))
+                    })?;
+                    let (numeric, components) = self.input_type(ty).ok_or_else(|| {
+                        format!(
+                            "the input `{}` isn't a scalar or vector of 32 bit numbers",
+                            self.name(id, ty)
+                        )
+                    })?;
+                    interface.inputs.push(VertexInput {
+                        location,
+                        name: self.name(id, ty),
+                        numeric,
+                        components,
+                    });
+                }
+                UNIFORM | UNIFORM_CONSTANT | STORAGE_BUFFER => {
+                    let (element, count) = match self.types.get(&ty) {
+                        Some(Type::Array { element, length }) => (
+                            *element,
+                            *self.constants.get(length).ok_or(Malformed)? as usize,
+                        ),
+                        Some(Type::RuntimeArray) => {
+                            return Err(format!(
+                                "`{}` is an unsized array, which isn't supported",
+                                self.name(id, ty)
+                            ))
+                        }
+                        _ => (ty, 1),
+                    };
+                    let (descriptor_type, size) = self.descriptor_type(element, storage)?;
+                    interface.bindings.push(Binding {
+                        set: decorations.set.unwrap_or(0),
+                        binding: decorations
+                            .binding
+                            .ok_or_else(|| format!("`{}` has no binding", self.name(id, ty)))?,
+                        name: self.name(id, ty),
+                        ty: descriptor_type,
+                        count,
+                        stages: stage,
+                        size,
+                    });
+                }
+                PUSH_CONSTANT => {
+                    interface.push_constants = Some(PushConstantBlock {
+                        stages: stage,
+                        size: self.size(ty, None)?,
+                    });
+                }
+                _ => {}
+            }
+        }
+        interface.inputs.sort_by_key(|input| input.location);
+        interface.bindings.sort_by_key(|b| (b.set, b.binding));
+        Ok(interface)
+    }
+
+    /// A variable's name, its type's for a block without an instance name, or its id
+    /// if the compiler didn't keep names.
+    fn name(&self, id: u32, ty: u32) -> String {
+        [id, ty]
+            .iter()
+            .filter_map(|id| self.names.get(id))
+            .find(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("%{}", id))
+    }
+
+    fn input_type(&self, ty: u32) -> Option<(Numeric, u32)> {
+        let (scalar, components) = match self.types.get(&ty)? {
+            Type::Vector { component, count } => (*component, *count),
+            _ => (ty, 1),
+        };
+        let numeric = match self.types.get(&scalar)? {
+            Type::Float { width: 32 } => Numeric::Float,
+            Type::Int {
+                width: 32,
+                signed: true,
+            } => Numeric::Sint,
+            Type::Int {
+                width: 32,
+                signed: false,
+            } => Numeric::Uint,
+            _ => return None,
+        };
+        Some((numeric, components))
+    }
+
+    /// The descriptor type for a resource of type `ty` in `storage`, and for uniform
+    /// blocks the size.
+    fn descriptor_type(
+        &self,
+        ty: u32,
+        storage: u32,
+    ) -> Result<(DescriptorType, Option<u32>), String> {
+        let decorations = self.decorations.get(&ty).cloned().unwrap_or_default();
+        let image_type = |dim: u32, sampled: u32| match (dim, sampled) {
+            (DIM_SUBPASS_DATA, _) => DescriptorType::InputAttachment,
+            (DIM_BUFFER, 2) => DescriptorType::StorageTexelBuffer,
+            (DIM_BUFFER, _) => DescriptorType::UniformTexelBuffer,
+            (_, 2) => DescriptorType::StorageImage,
+            _ => DescriptorType::SampledImage,
+        };
+        match (storage, self.types.get(&ty)) {
+            (UNIFORM, Some(Type::Struct { .. })) if decorations.block => {
+                Ok((DescriptorType::UniformBuffer, Some(self.size(ty, None)?)))
+            }
+            (UNIFORM, Some(Type::Struct { .. })) if decorations.buffer_block => {
+                Ok((DescriptorType::StorageBuffer, None))
+            }
+            (STORAGE_BUFFER, Some(Type::Struct { .. })) => {
+                Ok((DescriptorType::StorageBuffer, None))
+            }
+            (UNIFORM_CONSTANT, Some(Type::SampledImage { image })) => match self.types.get(image) {
+                Some(Type::Image {
+                    dim: DIM_BUFFER, ..
+                }) => Ok((DescriptorType::UniformTexelBuffer, None)),
+                _ => Ok((DescriptorType::CombinedImageSampler, None)),
+            },
+            (UNIFORM_CONSTANT, Some(Type::Image { dim, sampled })) => {
+                Ok((image_type(*dim, *sampled), None))
+            }
+            (UNIFORM_CONSTANT, Some(Type::Sampler)) => Ok((DescriptorType::Sampler, None)),
+            _ => Err(Malformed.into()),
+        }
+    }
+
+    /// How many bytes a value of type `ty` takes up in a block. Matrices inside
+    /// structs are spaced by their `matrix_stride`.
+    fn size(&self, ty: u32, matrix_stride: Option<u32>) -> Result<u32, Malformed> {
+        self.size_within(ty, matrix_stride, MAX_TYPE_DEPTH)
+    }
+
+    /// `size`, failing once types have nested more than `depth` deep, since a type
+    /// that contains itself would never finish.
+    fn size_within(
+        &self,
+        ty: u32,
+        matrix_stride: Option<u32>,
+        depth: u32,
+    ) -> Result<u32, Malformed> {
+        let depth = depth.checked_sub(1).ok_or(Malformed)?;
+        let size = match self.types.get(&ty).ok_or(Malformed)? {
+            Type::Bool => Some(4),
+            Type::Int { width, .. } | Type::Float { width } => Some(width / 8),
+            Type::Vector { component, count } => {
+                count.checked_mul(self.size_within(*component, None, depth)?)
+            }
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => count.checked_mul(stride),
+                None => count.checked_mul(self.size_within(*column, None, depth)?),
+            },
+            Type::Array { element, length } => {
+                let length = *self.constants.get(length).ok_or(Malformed)?;
+                let stride = match self.decorations.get(&ty).and_then(|d| d.array_stride) {
+                    Some(stride) => stride,
+                    None => self.size_within(*element, matrix_stride, depth)?,
+                };
+                length.checked_mul(stride)
+            }
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (i, member) in members.iter().enumerate() {
+                    let key = (ty, i as u32);
+                    let offset = self.member_offsets.get(&key).cloned().unwrap_or(0);
+                    let member_size =
+                        self.size_within(*member, self.matrix_strides.get(&key).cloned(), depth)?;
+                    size = size.max(offset.checked_add(member_size).ok_or(Malformed)?);
+                }
+                Some(size)
+            }
+            _ => None,
+        };
+        size.ok_or(Malformed)
+    }
+}
+
+/// A nul terminated literal string.
+fn string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::mesh::{BlockVertex, Vertex};
+
+    macro_rules! spirv {
+        ($name:expr) => {
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/spirv/", $name))
+        };
+    }
+
+    /// A module made of `instructions`, each an opcode and its operands.
+    fn module(instructions: &[(u32, &[u32])]) -> Vec<u8> {
+        let mut words = vec![MAGIC, 0x0001_0000, 0, 100, 0];
+        for (opcode, operands) in instructions {
+            words.push((operands.len() as u32 + 1) << 16 | opcode);
+            words.extend_from_slice(operands);
+        }
+        words
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn reflects_block_shaders() {
+        let vertex =
+            ShaderInterface::reflect(spirv!("block.vert.spv"), ShaderStageFlags::VERTEX).unwrap();
+        let fragment =
+            ShaderInterface::reflect(spirv!("block.frag.spv"), ShaderStageFlags::FRAGMENT).unwrap();
+
+        let inputs: Vec<_> = vertex
+            .inputs
+            .iter()
+            .map(|input| (input.location, input.numeric, input.components))
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                (0, Numeric::Float, 4),
+                (1, Numeric::Float, 2),
+                (2, Numeric::Uint, 1)
+            ]
+        );
+        assert_eq!(
+            vertex.push_constants,
+            Some(PushConstantBlock {
+                stages: ShaderStageFlags::VERTEX,
+                size: 64,
+            })
+        );
+
+        let mut interface = vertex.clone();
+        interface.merge(&fragment).unwrap();
+        let bindings: Vec<_> = interface
+            .bindings
+            .iter()
+            .map(|b| (b.set, b.binding, b.ty, b.count, b.stages, b.size))
+            .collect();
+        assert_eq!(
+            bindings,
+            [
+                (
+                    0,
+                    0,
+                    DescriptorType::UniformBuffer,
+                    1,
+                    ShaderStageFlags::VERTEX,
+                    // Two mat4s and a float.
+                    Some(132)
+                ),
+                (
+                    0,
+                    1,
+                    DescriptorType::CombinedImageSampler,
+                    1,
+                    ShaderStageFlags::FRAGMENT,
+                    None
+                ),
+            ]
+        );
+        assert_eq!(
+            interface
+                .vertex_attributes(&BlockVertex::layout())
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn built_in_shaders_fit_the_frame_layout() {
+        let frame = {
+            let mut frame =
+                ShaderInterface::reflect(spirv!("block.vert.spv"), ShaderStageFlags::VERTEX)
+                    .unwrap();
+            frame
+                .merge(
+                    &ShaderInterface::reflect(spirv!("block.frag.spv"), ShaderStageFlags::FRAGMENT)
+                        .unwrap(),
+                )
+                .unwrap();
+            frame
+        };
+        let shaders: &[(&[u8], &[u8])] = &[
+            (spirv!("plain.vert.spv"), spirv!("plain.frag.spv")),
+            (spirv!("debug.vert.spv"), spirv!("debug_normals.frag.spv")),
+            (spirv!("debug.vert.spv"), spirv!("debug_chunks.frag.spv")),
+            (spirv!("debug.vert.spv"), spirv!("debug_overdraw.frag.spv")),
+            (spirv!("debug_uv.vert.spv"), spirv!("debug_uv.frag.spv")),
+        ];
+        for (vertex, fragment) in shaders {
+            let mut interface = ShaderInterface::reflect(vertex, ShaderStageFlags::VERTEX).unwrap();
+            interface
+                .merge(&ShaderInterface::reflect(fragment, ShaderStageFlags::FRAGMENT).unwrap())
+                .unwrap();
+            interface.check_within(&frame).unwrap();
+            interface
+                .check_push_constants(&(ShaderStageFlags::VERTEX, 0..64))
+                .unwrap();
+            interface.vertex_attributes(&BlockVertex::layout()).unwrap();
+        }
+    }
+
+    #[test]
+    fn positions_can_be_given_fewer_components() {
+        let plain =
+            ShaderInterface::reflect(spirv!("plain.vert.spv"), ShaderStageFlags::VERTEX).unwrap();
+        plain.vertex_attributes(&<[f32; 2]>::layout()).unwrap();
+        plain.vertex_attributes(&<[f32; 3]>::layout()).unwrap();
+    }
+
+    #[test]
+    fn vertex_attributes_need_matching_component_counts() {
+        let interface = ShaderInterface {
+            inputs: vec![VertexInput {
+                location: 0,
+                name: "normal".to_string(),
+                numeric: Numeric::Float,
+                components: 3,
+            }],
+            ..ShaderInterface::default()
+        };
+        assert!(interface.vertex_attributes(&<[f32; 2]>::layout()).is_err());
+        assert!(interface.vertex_attributes(&<[f32; 3]>::layout()).is_ok());
+        let layout = VertexLayout {
+            stride: 12,
+            attributes: vec![(Format::Rgb32Uint, 0)],
+        };
+        assert!(interface.vertex_attributes(&layout).is_err());
+    }
+
+    #[test]
+    fn truncated_spirv_is_malformed() {
+        let spirv = spirv!("block.vert.spv");
+        for len in &[0, 3, 16, spirv.len() - 1] {
+            assert!(
+                ShaderInterface::reflect(&spirv[..*len], ShaderStageFlags::VERTEX).is_err(),
+                "{} bytes",
+                len
+            );
+        }
+
+        // Cut off in the middle of an instruction.
+        let spirv = module(&[(OP_TYPE_VECTOR, &[1, 2, 4])]);
+        assert!(
+            ShaderInterface::reflect(&spirv[..spirv.len() - 4], ShaderStageFlags::VERTEX).is_err()
+        );
+    }
+
+    #[test]
+    fn cyclic_types_are_malformed() {
+        // %1 is a vector of itself, read through a push constant.
+        let spirv = module(&[
+            (OP_TYPE_VECTOR, &[1, 1, 4]),
+            (OP_TYPE_POINTER, &[2, PUSH_CONSTANT, 1]),
+            (OP_VARIABLE, &[2, 3, PUSH_CONSTANT]),
+        ]);
+        assert!(ShaderInterface::reflect(&spirv, ShaderStageFlags::VERTEX).is_err());
+
+        // And a struct holding an array of the struct.
+        let spirv = module(&[
+            (OP_TYPE_INT, &[1, 32, 0]),
+            (OP_CONSTANT, &[1, 2, 4]),
+            (OP_TYPE_STRUCT, &[3, 4]),
+            (OP_TYPE_ARRAY, &[4, 3, 2]),
+            (OP_TYPE_POINTER, &[5, PUSH_CONSTANT, 3]),
+            (OP_VARIABLE, &[5, 6, PUSH_CONSTANT]),
+        ]);
+        assert!(ShaderInterface::reflect(&spirv, ShaderStageFlags::VERTEX).is_err());
+    }
+
+    #[test]
+    fn oversized_types_are_malformed() {
+        let spirv = module(&[
+            (OP_TYPE_FLOAT, &[1, 32]),
+            (OP_TYPE_INT, &[2, 32, 0]),
+            (OP_CONSTANT, &[2, 3, u32::MAX]),
+            (OP_TYPE_ARRAY, &[4, 1, 3]),
+            (OP_TYPE_POINTER, &[5, PUSH_CONSTANT, 4]),
+            (OP_VARIABLE, &[5, 6, PUSH_CONSTANT]),
+        ]);
+        assert!(ShaderInterface::reflect(&spirv, ShaderStageFlags::VERTEX).is_err());
+    }
+}
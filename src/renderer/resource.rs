@@ -0,0 +1,426 @@
+//! gfx-hal objects that destroy themselves.
+//!
+//! Everything made from the device holds a `DeviceHandle` to it. Dropping one of the
+//! wrappers here doesn't destroy the object on the spot, since a frame the GPU is
+//! still working on may use it. It's retired instead, tagged with the next frame to
+//! be submitted, and destroyed once that frame's fence has been waited on. Memory
+//! goes back to the `Allocator` at the same time.
+//!
+//! The next frame rather than the last one, because uploads queued for the object
+//! are only submitted when the next frame begins.
+
+use super::{
+    alloc::{Allocation, Allocator, MemoryUsage, ResourceKind},
+    back, RendererError,
+};
+
+use gfx_hal::{
+    buffer,
+    device::Device,
+    format::Format,
+    image::{Kind, Tiling, Usage, ViewCapabilities},
+    pool::CommandPool as TypedCommandPool,
+    Backend, Graphics,
+};
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
+
+/// The device, shared by everything made from it. It's destroyed once the last
+/// handle and every object retired through it are gone.
+#[derive(Clone)]
+pub struct DeviceHandle(Rc<Shared>);
+
+struct Shared {
+    raw: back::Device,
+    /// How many frames have been submitted.
+    submitted: Cell<u64>,
+    /// Retired objects and the frame that has to finish before they can go, oldest
+    /// first.
+    retired: RefCell<VecDeque<(u64, Retired)>>,
+    /// Set once the device is idle for good, after which objects are destroyed as
+    /// soon as they're dropped.
+    torn_down: Cell<bool>,
+}
+
+impl DeviceHandle {
+    pub fn new(device: back::Device) -> Self {
+        DeviceHandle(Rc::new(Shared {
+            raw: device,
+            submitted: Cell::new(0),
+            retired: RefCell::new(VecDeque::new()),
+            torn_down: Cell::new(false),
+        }))
+    }
+
+    /// Counts a frame as submitted, returning its number. Anything retired before
+    /// this can go once that frame's fence has been waited on.
+    pub fn frame_submitted(&self) -> u64 {
+        let frame = self.0.submitted.get() + 1;
+        self.0.submitted.set(frame);
+        frame
+    }
+
+    /// The number of the last frame submitted.
+    pub fn submitted(&self) -> u64 {
+        self.0.submitted.get()
+    }
+
+    /// Destroys everything waiting on frame `completed` or earlier. Only call this
+    /// once that frame's fence has been waited on.
+    pub fn collect(&self, completed: u64, allocator: &mut Allocator) {
+        let mut retired = self.0.retired.borrow_mut();
+        while retired
+            .front()
+            .is_some_and(|(frame, _)| *frame <= completed)
+        {
+            let (_, object) = retired.pop_front().unwrap();
+            unsafe { object.destroy(&self.0.raw, Some(&mut *allocator)) };
+        }
+    }
+
+    /// Waits for the GPU to finish everything submitted, then destroys whatever that
+    /// was holding up. Uploads not submitted yet still hold on to theirs.
+    pub fn wait_idle_and_collect(&self, allocator: &mut Allocator) -> Result<(), RendererError> {
+        self.0.raw.wait_idle()?;
+        self.collect(self.submitted(), allocator);
+        Ok(())
+    }
+
+    /// Destroys everything retired so far and everything dropped from now on right
+    /// away. Only call this once the device is idle and nothing more will be
+    /// submitted. Memory of objects dropped after this is left to the `Allocator`
+    /// to release along with its blocks.
+    pub fn tear_down(&self, allocator: &mut Allocator) {
+        self.collect(u64::MAX, allocator);
+        self.0.torn_down.set(true);
+    }
+
+    fn retire(&self, object: Retired) {
+        if self.0.torn_down.get() {
+            unsafe { object.destroy(&self.0.raw, None) };
+        } else {
+            let frame = self.0.submitted.get() + 1;
+            self.0.retired.borrow_mut().push_back((frame, object));
+        }
+    }
+}
+
+impl Deref for DeviceHandle {
+    type Target = back::Device;
+
+    fn deref(&self) -> &back::Device {
+        &self.0.raw
+    }
+}
+
+impl Drop for Shared {
+    /// Anything still retired has nothing left that could use it. Its memory went
+    /// with the `Allocator`, which held a handle until then.
+    fn drop(&mut self) {
+        for (_, object) in self.retired.get_mut().drain(..) {
+            unsafe { object.destroy(&self.raw, None) };
+        }
+    }
+}
+
+/// An object waiting for the GPU to be done with it.
+enum Retired {
+    Buffer(<back::Backend as Backend>::Buffer, Allocation),
+    Image(<back::Backend as Backend>::Image, Allocation),
+    ImageView(<back::Backend as Backend>::ImageView),
+    Sampler(<back::Backend as Backend>::Sampler),
+    ShaderModule(<back::Backend as Backend>::ShaderModule),
+    Framebuffer(<back::Backend as Backend>::Framebuffer),
+    RenderPass(<back::Backend as Backend>::RenderPass),
+    GraphicsPipeline(<back::Backend as Backend>::GraphicsPipeline),
+    PipelineLayout(<back::Backend as Backend>::PipelineLayout),
+    PipelineCache(<back::Backend as Backend>::PipelineCache),
+    DescriptorSetLayout(<back::Backend as Backend>::DescriptorSetLayout),
+    DescriptorPool(<back::Backend as Backend>::DescriptorPool),
+    CommandPool(TypedCommandPool<back::Backend, Graphics>),
+    Fence(<back::Backend as Backend>::Fence),
+    Semaphore(<back::Backend as Backend>::Semaphore),
+    Swapchain(<back::Backend as Backend>::Swapchain),
+}
+
+impl Retired {
+    unsafe fn destroy(self, device: &back::Device, allocator: Option<&mut Allocator>) {
+        let allocation = match self {
+            Retired::Buffer(buffer, allocation) => {
+                device.destroy_buffer(buffer);
+                Some(allocation)
+            }
+            Retired::Image(image, allocation) => {
+                device.destroy_image(image);
+                Some(allocation)
+            }
+            Retired::ImageView(view) => {
+                device.destroy_image_view(view);
+                None
+            }
+            Retired::Sampler(sampler) => {
+                device.destroy_sampler(sampler);
+                None
+            }
+            Retired::ShaderModule(module) => {
+                device.destroy_shader_module(module);
+                None
+            }
+            Retired::Framebuffer(framebuffer) => {
+                device.destroy_framebuffer(framebuffer);
+                None
+            }
+            Retired::RenderPass(render_pass) => {
+                device.destroy_render_pass(render_pass);
+                None
+            }
+            Retired::GraphicsPipeline(pipeline) => {
+                device.destroy_graphics_pipeline(pipeline);
+                None
+            }
+            Retired::PipelineLayout(layout) => {
+                device.destroy_pipeline_layout(layout);
+                None
+            }
+            Retired::PipelineCache(cache) => {
+                device.destroy_pipeline_cache(cache);
+                None
+            }
+            Retired::DescriptorSetLayout(layout) => {
+                device.destroy_descriptor_set_layout(layout);
+                None
+            }
+            Retired::DescriptorPool(pool) => {
+                device.destroy_descriptor_pool(pool);
+                None
+            }
+            Retired::CommandPool(pool) => {
+                device.destroy_command_pool(pool.into_raw());
+                None
+            }
+            Retired::Fence(fence) => {
+                device.destroy_fence(fence);
+                None
+            }
+            Retired::Semaphore(semaphore) => {
+                device.destroy_semaphore(semaphore);
+                None
+            }
+            Retired::Swapchain(swapchain) => {
+                device.destroy_swapchain(swapchain);
+                None
+            }
+        };
+        if let (Some(allocation), Some(allocator)) = (allocation, allocator) {
+            allocator.free(allocation);
+        }
+    }
+}
+
+/// Defines a wrapper that derefs to the raw object and retires it when dropped.
+macro_rules! owned {
+    ($(#[$attr:meta])* $name:ident($raw:ty)) => {
+        $(#[$attr])*
+        pub struct $name {
+            raw: Option<$raw>,
+            device: DeviceHandle,
+        }
+
+        impl $name {
+            pub fn new(device: &DeviceHandle, raw: $raw) -> Self {
+                Self {
+                    raw: Some(raw),
+                    device: device.clone(),
+                }
+            }
+        }
+
+        impl Deref for $name {
+            type Target = $raw;
+
+            fn deref(&self) -> &$raw {
+                self.raw.as_ref().unwrap()
+            }
+        }
+
+        impl DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut $raw {
+                self.raw.as_mut().unwrap()
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                if let Some(raw) = self.raw.take() {
+                    self.device.retire(Retired::$name(raw));
+                }
+            }
+        }
+    };
+}
+
+owned!(ImageView(<back::Backend as Backend>::ImageView));
+owned!(Sampler(<back::Backend as Backend>::Sampler));
+owned!(ShaderModule(<back::Backend as Backend>::ShaderModule));
+owned!(Framebuffer(<back::Backend as Backend>::Framebuffer));
+owned!(RenderPass(<back::Backend as Backend>::RenderPass));
+owned!(GraphicsPipeline(
+    <back::Backend as Backend>::GraphicsPipeline
+));
+owned!(PipelineLayout(<back::Backend as Backend>::PipelineLayout));
+owned!(PipelineCache(<back::Backend as Backend>::PipelineCache));
+owned!(DescriptorSetLayout(
+    <back::Backend as Backend>::DescriptorSetLayout
+));
+owned!(
+    /// Command buffers from the pool go with it.
+    CommandPool(TypedCommandPool<back::Backend, Graphics>)
+);
+owned!(
+    /// Descriptor sets from the pool go with it.
+    DescriptorPool(<back::Backend as Backend>::DescriptorPool)
+);
+owned!(Fence(<back::Backend as Backend>::Fence));
+owned!(Semaphore(<back::Backend as Backend>::Semaphore));
+owned!(
+    /// Its images are owned by the swapchain itself.
+    Swapchain(<back::Backend as Backend>::Swapchain)
+);
+
+impl Swapchain {
+    /// Takes the swapchain back out, for creating its replacement from.
+    pub fn into_raw(mut self) -> <back::Backend as Backend>::Swapchain {
+        self.raw.take().unwrap()
+    }
+}
+
+/// A buffer and the memory bound to it.
+pub struct Buffer {
+    raw: Option<(<back::Backend as Backend>::Buffer, Allocation)>,
+    device: DeviceHandle,
+}
+
+impl Buffer {
+    /// Creates a buffer of `size` bytes and binds it to memory for `memory_usage`.
+    pub fn new(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        size: u64,
+        usage: buffer::Usage,
+        memory_usage: MemoryUsage,
+    ) -> Result<Self, RendererError> {
+        unsafe {
+            let mut buffer = device.create_buffer(size, usage)?;
+            let requirements = device.get_buffer_requirements(&buffer);
+            let allocation =
+                match allocator.allocate(requirements, memory_usage, ResourceKind::Buffer) {
+                    Ok(allocation) => allocation,
+                    Err(e) => {
+                        device.destroy_buffer(buffer);
+                        return Err(e);
+                    }
+                };
+            let memory = allocator.memory(&allocation);
+            if let Err(e) = device.bind_buffer_memory(memory, allocation.offset, &mut buffer) {
+                device.destroy_buffer(buffer);
+                allocator.free(allocation);
+                return Err(e.into());
+            }
+            Ok(Self {
+                raw: Some((buffer, allocation)),
+                device: device.clone(),
+            })
+        }
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        &self.raw.as_ref().unwrap().1
+    }
+}
+
+impl Deref for Buffer {
+    type Target = <back::Backend as Backend>::Buffer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.raw.as_ref().unwrap().0
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some((buffer, allocation)) = self.raw.take() {
+            self.device.retire(Retired::Buffer(buffer, allocation));
+        }
+    }
+}
+
+/// An optimally tiled image in device local memory.
+pub struct Image {
+    raw: Option<(<back::Backend as Backend>::Image, Allocation)>,
+    device: DeviceHandle,
+}
+
+impl Image {
+    pub fn new(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        kind: Kind,
+        levels: u8,
+        format: Format,
+        usage: Usage,
+    ) -> Result<Self, RendererError> {
+        unsafe {
+            let mut image = device.create_image(
+                kind,
+                levels,
+                format,
+                Tiling::Optimal,
+                usage,
+                ViewCapabilities::empty(),
+            )?;
+            let requirements = device.get_image_requirements(&image);
+            let allocation = match allocator.allocate(
+                requirements,
+                MemoryUsage::DeviceLocal,
+                ResourceKind::Image,
+            ) {
+                Ok(allocation) => allocation,
+                Err(e) => {
+                    device.destroy_image(image);
+                    return Err(e);
+                }
+            };
+            let memory = allocator.memory(&allocation);
+            if let Err(e) = device.bind_image_memory(memory, allocation.offset, &mut image) {
+                device.destroy_image(image);
+                allocator.free(allocation);
+                return Err(e.into());
+            }
+            Ok(Self {
+                raw: Some((image, allocation)),
+                device: device.clone(),
+            })
+        }
+    }
+}
+
+impl Deref for Image {
+    type Target = <back::Backend as Backend>::Image;
+
+    fn deref(&self) -> &Self::Target {
+        &self.raw.as_ref().unwrap().0
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if let Some((image, allocation)) = self.raw.take() {
+            self.device.retire(Retired::Image(image, allocation));
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! The GLSL shaders in `assets/shaders`.
+//!
+//! Normally their SPIR-V, committed in `assets/spirv`, is embedded in the binary.
+//! With the `runtime-shaders` feature they're compiled with shaderc when a pipeline
+//! is built instead, and a `ShaderWatcher` can tell which pipelines need rebuilding
+//! after a change, since every file a shader pulls in is remembered.
+//!
+//! `#include "file"` is looked up next to the including file and `#include <file>`
+//! in `SHADER_DIR`.
+
+use super::RendererError;
+
+use std::fmt;
+
+#[cfg(feature = "runtime-shaders")]
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+#[cfg(not(feature = "runtime-shaders"))]
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// Where the shaders are read from.
+#[cfg(feature = "runtime-shaders")]
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shaders");
+
+/// How often a `ShaderWatcher` looks at the files again.
+#[cfg(feature = "runtime-shaders")]
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The vertex and fragment shader a pipeline is built from, by path in `SHADER_DIR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderSet {
+    pub vertex: &'static str,
+    pub fragment: &'static str,
+}
+
+impl fmt::Display for ShaderSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} and {}", self.vertex, self.fragment)
+    }
+}
+
+/// Positions only, shaded by depth.
+pub const PLAIN_SHADERS: ShaderSet = ShaderSet {
+    vertex: "plain.vert",
+    fragment: "plain.frag",
+};
+
+/// `BlockVertex`es, textured from the block texture array.
+pub const BLOCK_SHADERS: ShaderSet = ShaderSet {
+    vertex: "block.vert",
+    fragment: "block.frag",
+};
+
+/// Positions only, colored by which way each face points. See `DebugView`.
+pub const DEBUG_NORMAL_SHADERS: ShaderSet = ShaderSet {
+    vertex: "debug.vert",
+    fragment: "debug_normals.frag",
+};
+
+/// `BlockVertex`es, colored by their texture coordinates.
+pub const DEBUG_UV_SHADERS: ShaderSet = ShaderSet {
+    vertex: "debug_uv.vert",
+    fragment: "debug_uv.frag",
+};
+
+/// Positions only, with a color for every model matrix, so each chunk gets its own.
+pub const DEBUG_CHUNK_SHADERS: ShaderSet = ShaderSet {
+    vertex: "debug.vert",
+    fragment: "debug_chunks.frag",
+};
+
+/// Positions only, adding a little to every pixel each time it's drawn to.
+pub const DEBUG_OVERDRAW_SHADERS: ShaderSet = ShaderSet {
+    vertex: "debug.vert",
+    fragment: "debug_overdraw.frag",
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderKind {
+    Vertex,
+    Fragment,
+}
+
+/// Every shader set the renderer builds pipelines from. The pipeline layout they
+/// share is made to fit all of them.
+pub const BUILT_IN_SHADERS: [ShaderSet; 6] = [
+    PLAIN_SHADERS,
+    BLOCK_SHADERS,
+    DEBUG_NORMAL_SHADERS,
+    DEBUG_UV_SHADERS,
+    DEBUG_CHUNK_SHADERS,
+    DEBUG_OVERDRAW_SHADERS,
+];
+
+/// A compiled shader and, when compiled at runtime, every file that went into it.
+pub struct CompiledShader {
+    pub spirv: Vec<u8>,
+    #[cfg(feature = "runtime-shaders")]
+    pub sources: Vec<PathBuf>,
+}
+
+/// Hands out SPIR-V for the shaders by name, compiling them first with the
+/// `runtime-shaders` feature.
+pub struct ShaderCompiler {
+    #[cfg(feature = "runtime-shaders")]
+    compiler: shaderc::Compiler,
+}
+
+impl ShaderCompiler {
+    pub fn new() -> Result<Self, RendererError> {
+        Ok(Self {
+            #[cfg(feature = "runtime-shaders")]
+            compiler: shaderc::Compiler::new().ok_or(RendererError::Setup("shaderc not found!"))?,
+        })
+    }
+
+    /// Compiles `name` from `SHADER_DIR`. Diagnostics name the file and line they're
+    /// about.
+    #[cfg(feature = "runtime-shaders")]
+    pub fn compile(
+        &mut self,
+        name: &str,
+        kind: ShaderKind,
+    ) -> Result<CompiledShader, RendererError> {
+        let kind = match kind {
+            ShaderKind::Vertex => shaderc::ShaderKind::Vertex,
+            ShaderKind::Fragment => shaderc::ShaderKind::Fragment,
+        };
+        compile(&mut self.compiler, name, kind)
+    }
+
+    /// The SPIR-V embedded for `name`.
+    #[cfg(not(feature = "runtime-shaders"))]
+    pub fn compile(
+        &mut self,
+        name: &str,
+        _kind: ShaderKind,
+    ) -> Result<CompiledShader, RendererError> {
+        embedded::EMBEDDED
+            .iter()
+            .find(|(embedded, _)| *embedded == name)
+            .map(|(_, spirv)| CompiledShader {
+                spirv: spirv.to_vec(),
+            })
+            .ok_or_else(|| RendererError::ShaderCompile {
+                name: name.to_string(),
+                diagnostics: "There's no SPIR-V for it in assets/spirv.".to_string(),
+            })
+    }
+}
+
+#[cfg(feature = "runtime-shaders")]
+fn compile(
+    compiler: &mut shaderc::Compiler,
+    name: &str,
+    kind: shaderc::ShaderKind,
+) -> Result<CompiledShader, RendererError> {
+    let path = normalize(&Path::new(SHADER_DIR).join(name));
+    let source = fs::read_to_string(&path)?;
+    let file_name = path.display().to_string();
+    let sources = RefCell::new(vec![path]);
+    let artifact = {
+        let mut options = shaderc::CompileOptions::new().ok_or(RendererError::Setup(
+            "Couldn't make shaderc compile options!",
+        ))?;
+        options.set_include_callback(|requested, include_type, requesting, _depth| {
+            let base = match include_type {
+                shaderc::IncludeType::Relative => Path::new(requesting)
+                    .parent()
+                    .unwrap_or_else(|| Path::new(SHADER_DIR))
+                    .to_path_buf(),
+                shaderc::IncludeType::Standard => PathBuf::from(SHADER_DIR),
+            };
+            let path = normalize(&base.join(requested));
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+            let resolved_name = path.display().to_string();
+            sources.borrow_mut().push(path);
+            Ok(shaderc::ResolvedInclude {
+                resolved_name,
+                content,
+            })
+        });
+        compiler
+            .compile_into_spirv(&source, kind, &file_name, "main", Some(&options))
+            .map_err(|e| RendererError::ShaderCompile {
+                name: name.to_string(),
+                diagnostics: e.to_string(),
+            })?
+    };
+    Ok(CompiledShader {
+        spirv: artifact.as_binary_u8().to_vec(),
+        sources: sources.into_inner(),
+    })
+}
+
+/// Looks for shader files being added, changed or removed by polling their
+/// modification times.
+#[cfg(feature = "runtime-shaders")]
+pub struct ShaderWatcher {
+    modified: HashMap<PathBuf, SystemTime>,
+    next_poll: Instant,
+}
+
+#[cfg(feature = "runtime-shaders")]
+impl Default for ShaderWatcher {
+    fn default() -> Self {
+        Self {
+            modified: scan(Path::new(SHADER_DIR)),
+            next_poll: Instant::now() + POLL_INTERVAL,
+        }
+    }
+}
+
+#[cfg(feature = "runtime-shaders")]
+impl ShaderWatcher {
+    /// The files that changed since the last call. Returns nothing until
+    /// `POLL_INTERVAL` has passed, so it's cheap enough to call every frame.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        if now < self.next_poll {
+            return Vec::new();
+        }
+        self.next_poll = now + POLL_INTERVAL;
+
+        let modified = scan(Path::new(SHADER_DIR));
+        let mut changed: Vec<PathBuf> = modified
+            .iter()
+            .filter(|(path, time)| self.modified.get(*path) != Some(time))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(
+            self.modified
+                .keys()
+                .filter(|path| !modified.contains_key(*path))
+                .cloned(),
+        );
+        self.modified = modified;
+        changed
+    }
+}
+
+/// The modification time of every file under `dir`. Anything that can't be read is
+/// left out, which looks the same as it being removed.
+#[cfg(feature = "runtime-shaders")]
+fn scan(dir: &Path) -> HashMap<PathBuf, SystemTime> {
+    let mut modified = HashMap::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return modified,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => modified.extend(scan(&path)),
+            Ok(metadata) => {
+                if let Ok(time) = metadata.modified() {
+                    modified.insert(normalize(&path), time);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+    modified
+}
+
+/// The same file always gets the same path, whichever way it was reached.
+#[cfg(feature = "runtime-shaders")]
+fn normalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
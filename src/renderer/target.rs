@@ -0,0 +1,89 @@
+use super::{
+    alloc::Allocator,
+    back,
+    resource::{DeviceHandle, Image, Swapchain},
+    RendererError,
+};
+
+use gfx_hal::{
+    format::Format,
+    image::{Kind, Layout, Usage},
+    window::{Extent2D, PresentMode},
+    Backend,
+};
+
+/// Headless targets always render in this format so `read_pixels` can hand
+/// back RGBA8 without converting anything.
+pub const OFFSCREEN_FORMAT: Format = Format::Rgba8Srgb;
+
+/// Where the frames we draw end up.
+pub enum Target {
+    Window(WindowTarget),
+    Offscreen(OffscreenTarget),
+}
+
+impl Target {
+    /// The images framebuffers get built from, one per possible acquired index.
+    pub fn images(&self) -> &[<back::Backend as Backend>::Image] {
+        match self {
+            Target::Window(window) => &window.images,
+            Target::Offscreen(offscreen) => std::slice::from_ref(&*offscreen.image),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            Target::Window(window) => window.format,
+            Target::Offscreen(_) => OFFSCREEN_FORMAT,
+        }
+    }
+
+    /// The layout the render pass leaves the color attachment in.
+    pub fn final_layout(&self) -> Layout {
+        match self {
+            Target::Window(_) => Layout::Present,
+            Target::Offscreen(_) => Layout::TransferSrcOptimal,
+        }
+    }
+}
+
+/// A window surface and the swapchain presenting to it.
+pub struct WindowTarget {
+    /// `None` while the window is minimized. Declared before the surface, since it
+    /// has to go first.
+    pub swapchain: Option<Swapchain>,
+    pub surface: <back::Backend as Backend>::Surface,
+    /// The swapchain images, owned by the swapchain itself.
+    pub images: Vec<<back::Backend as Backend>::Image>,
+    pub format: Format,
+    /// What the swapchain images can be used for, `TRANSFER_SRC` included if captures work.
+    pub usage: Usage,
+    /// What the swapchain was last made with, which can differ from what was asked for.
+    pub present_mode: PresentMode,
+    pub suboptimal: bool,
+    /// Last known client area of the window in physical pixels.
+    pub window_extent: Extent2D,
+}
+
+/// A single color image we render into when there is no window.
+pub struct OffscreenTarget {
+    pub image: Image,
+}
+
+impl OffscreenTarget {
+    pub fn new(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        extent: Extent2D,
+    ) -> Result<Self, RendererError> {
+        let image = Image::new(
+            device,
+            allocator,
+            Kind::D2(extent.width, extent.height, 1, 1),
+            1,
+            OFFSCREEN_FORMAT,
+            Usage::COLOR_ATTACHMENT | Usage::TRANSFER_SRC,
+        )?;
+        Ok(Self { image })
+    }
+}
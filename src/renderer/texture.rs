@@ -0,0 +1,252 @@
+use super::{
+    alloc::Allocator,
+    back,
+    pixels::RgbaImage,
+    resource::{DeviceHandle, Image, ImageView, Sampler},
+    upload::Uploader,
+    RendererError,
+};
+
+use gfx_hal::{
+    device::Device,
+    format::{Aspects, Format, Swizzle},
+    image::{
+        Anisotropic, Extent, Filter, Kind, SamplerInfo, SubresourceRange, Usage, ViewKind, WrapMode,
+    },
+    queue::CommandQueue,
+    Graphics,
+};
+
+/// Block textures are sRGB, like the PNGs they come from.
+const TEXTURE_FORMAT: Format = Format::Rgba8Srgb;
+
+/// Anisotropic filtering goes no higher than this, even where the device allows it.
+pub const MAX_ANISOTROPY: f32 = 16.0;
+
+/// Same sized images stacked into the layers of one image, with a full mip chain.
+/// Blocks pick their face's texture by layer, so every face can be drawn with the
+/// same descriptor set.
+pub struct TextureArray {
+    pub view: ImageView,
+    pub image: Image,
+    pub layers: u16,
+}
+
+impl TextureArray {
+    /// Creates the image in device local memory and queues uploads of every level of
+    /// every layer. Mipmaps are made on the CPU by averaging 2x2 texels.
+    pub fn new(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        uploader: &mut Uploader,
+        queue: &mut CommandQueue<back::Backend, Graphics>,
+        layers: &[RgbaImage],
+    ) -> Result<Self, RendererError> {
+        let first = layers.first().ok_or(RendererError::InvalidArgument(
+            "A texture array needs at least one layer!",
+        ))?;
+        let (width, height) = (first.width, first.height);
+        if width == 0 || height == 0 {
+            return Err(RendererError::InvalidArgument("Textures can't be empty!"));
+        }
+        if layers
+            .iter()
+            .any(|layer| layer.width != width || layer.height != height)
+        {
+            return Err(RendererError::InvalidArgument(
+                "Every layer of a texture array has to be the same size!",
+            ));
+        }
+        if layers.len() > u16::MAX as usize {
+            return Err(RendererError::InvalidArgument(
+                "Too many layers for one texture array!",
+            ));
+        }
+        let layer_count = layers.len() as u16;
+        let chains: Vec<Vec<RgbaImage>> = layers.iter().map(mip_chain).collect();
+        let levels = chains[0].len() as u8;
+
+        let image = Image::new(
+            device,
+            allocator,
+            Kind::D2(width, height, layer_count, 1),
+            levels,
+            TEXTURE_FORMAT,
+            Usage::SAMPLED | Usage::TRANSFER_DST,
+        )?;
+        let view = unsafe {
+            device.create_image_view(
+                &image,
+                ViewKind::D2Array,
+                TEXTURE_FORMAT,
+                Swizzle::NO,
+                SubresourceRange {
+                    aspects: Aspects::COLOR,
+                    levels: 0..levels,
+                    layers: 0..layer_count,
+                },
+            )?
+        };
+        let texture = Self {
+            view: ImageView::new(device, view),
+            image,
+            layers: layer_count,
+        };
+
+        // One upload per level, holding that level of every layer back to back. If one
+        // fails the others may already be recorded, which is fine since the image is
+        // only destroyed once the frame they go out with is done.
+        for level in 0..levels {
+            let mip = &chains[0][level as usize];
+            let data: Vec<u8> = chains
+                .iter()
+                .flat_map(|chain| chain[level as usize].pixels.iter().cloned())
+                .collect();
+            unsafe {
+                uploader.upload_image(
+                    device,
+                    allocator,
+                    queue,
+                    &data,
+                    &texture.image,
+                    Extent {
+                        width: mip.width,
+                        height: mip.height,
+                        depth: 1,
+                    },
+                    level,
+                    0..layer_count,
+                )?;
+            }
+        }
+        Ok(texture)
+    }
+}
+
+/// Crisp texels up close, blended mip levels in the distance, and repeating so a
+/// face can tile its texture. `anisotropic` keeps faces seen at a glance sharp, and
+/// needs `Features::SAMPLER_ANISOTROPY`.
+pub fn create_sampler(
+    device: &DeviceHandle,
+    anisotropic: Anisotropic,
+) -> Result<Sampler, RendererError> {
+    let info = SamplerInfo {
+        mip_filter: Filter::Linear,
+        anisotropic,
+        ..SamplerInfo::new(Filter::Nearest, WrapMode::Tile)
+    };
+    let sampler = unsafe { device.create_sampler(info)? };
+    Ok(Sampler::new(device, sampler))
+}
+
+/// `image` followed by each of its mip levels, down to 1x1.
+fn mip_chain(image: &RgbaImage) -> Vec<RgbaImage> {
+    let mut chain = vec![image.clone()];
+    loop {
+        let last = chain.last().unwrap();
+        if last.width == 1 && last.height == 1 {
+            return chain;
+        }
+        let next = downsample(last);
+        chain.push(next);
+    }
+}
+
+/// Halves `image` in each dimension, averaging each 2x2 block of texels. An odd sized
+/// image loses its last row or column, and a dimension already down to 1 stays there.
+///
+/// The color channels are sRGB encoded, like `TEXTURE_FORMAT`, so they're averaged as
+/// the light they stand for. Averaging the encoded values would darken every level.
+/// Alpha is linear already.
+fn downsample(image: &RgbaImage) -> RgbaImage {
+    let to_linear: Vec<f32> = (0..=255u8).map(srgb_to_linear).collect();
+    let width = (image.width / 2).max(1);
+    let height = (image.height / 2).max(1);
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        let (y0, y1) = (y * 2, (y * 2 + 1).min(image.height - 1));
+        for x in 0..width {
+            let (x0, x1) = (x * 2, (x * 2 + 1).min(image.width - 1));
+            let texels = [
+                image.pixel(x0, y0),
+                image.pixel(x1, y0),
+                image.pixel(x0, y1),
+                image.pixel(x1, y1),
+            ];
+            for channel in 0..3 {
+                let sum: f32 = texels
+                    .iter()
+                    .map(|texel| to_linear[usize::from(texel[channel])])
+                    .sum();
+                pixels.push(linear_to_srgb(sum / 4.0));
+            }
+            let alpha: u32 = texels.iter().map(|texel| u32::from(texel[3])).sum();
+            pixels.push(((alpha + 2) / 4) as u8);
+        }
+    }
+    RgbaImage::new(width, height, pixels)
+}
+
+/// An sRGB encoded channel as linear light, from 0 to 1.
+fn srgb_to_linear(encoded: u8) -> f32 {
+    let c = f32::from(encoded) / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light from 0 to 1 as an sRGB encoded channel.
+fn linear_to_srgb(linear: f32) -> u8 {
+    let c = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips() {
+        for encoded in 0..=255u8 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(encoded)), encoded);
+        }
+    }
+
+    #[test]
+    fn downsampling_averages_light() {
+        // Black and white checkers average to half the light, which sRGB encodes
+        // well above half way.
+        let checkers = RgbaImage::new(
+            2,
+            2,
+            vec![
+                0, 0, 0, 255, 255, 255, 255, 255, //
+                255, 255, 255, 255, 0, 0, 0, 255,
+            ],
+        );
+        assert_eq!(downsample(&checkers).pixels, vec![188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn downsampling_keeps_flat_colors_and_averages_alpha() {
+        let texel = |alpha| vec![200, 100, 30, alpha];
+        let image = RgbaImage::new(2, 2, [texel(0), texel(255), texel(0), texel(255)].concat());
+        assert_eq!(downsample(&image).pixels, vec![200, 100, 30, 128]);
+    }
+
+    #[test]
+    fn mip_chains_go_down_to_one_texel() {
+        let image = RgbaImage::new(8, 2, vec![255; 8 * 2 * 4]);
+        let sizes: Vec<_> = mip_chain(&image)
+            .iter()
+            .map(|mip| (mip.width, mip.height))
+            .collect();
+        assert_eq!(sizes, vec![(8, 2), (4, 1), (2, 1), (1, 1)]);
+    }
+}
@@ -0,0 +1,351 @@
+//! Getting data into `DEVICE_LOCAL` memory.
+//!
+//! Data is copied into a ring of host visible staging memory and copy commands
+//! for it are recorded into a batch. Batches are submitted before the next frame
+//! on the same queue, so anything uploaded is in place by the time a frame uses
+//! it. Each batch has a fence, and its part of the ring is reused once that fence
+//! has been signalled.
+
+use super::{
+    alloc::{align_up, Allocator, MemoryUsage},
+    back,
+    resource::{Buffer, CommandPool, DeviceHandle, Fence},
+    RendererError,
+};
+
+use gfx_hal::{
+    buffer,
+    command::{BufferCopy, BufferImageCopy, CommandBuffer, OneShot, Primary},
+    device::Device,
+    format::Aspects,
+    image::{Access, Extent, Layout, Offset, SubresourceLayers, SubresourceRange},
+    memory::{Barrier, Dependencies},
+    pool::CommandPoolCreateFlags,
+    pso::PipelineStage,
+    queue::{CommandQueue, QueueGroup},
+    Backend, Graphics,
+};
+
+use std::{collections::VecDeque, ops::Range};
+
+/// Size of the staging ring. Uploads bigger than a quarter of it get a staging
+/// buffer of their own instead.
+pub const STAGING_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Covers the texel size of every format we upload and `optimalBufferCopyOffsetAlignment`
+/// on the hardware we know of.
+const STAGING_ALIGNMENT: u64 = 16;
+
+/// Copy commands sharing a submission and a fence.
+struct Batch {
+    command_buffer: CommandBuffer<back::Backend, Graphics, OneShot, Primary>,
+    /// Where the ring's head was once this batch was submitted.
+    ring_end: u64,
+    /// Staging buffers for uploads too big for the ring.
+    dedicated: Vec<Buffer>,
+    fence: Option<Fence>,
+}
+
+/// Whatever is still recording or in flight when this is dropped gets destroyed once
+/// the GPU is done with it, like everything else.
+pub struct Uploader {
+    command_pool: CommandPool,
+    staging: Buffer,
+    head: u64,
+    tail: u64,
+    /// The batch being recorded, if anything was uploaded since the last flush.
+    recording: Option<Batch>,
+    /// Submitted batches, oldest first.
+    in_flight: VecDeque<Batch>,
+}
+
+impl Uploader {
+    pub fn new(
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        queue_group: &QueueGroup<back::Backend, Graphics>,
+    ) -> Result<Self, RendererError> {
+        let command_pool = unsafe {
+            device.create_command_pool_typed(queue_group, CommandPoolCreateFlags::TRANSIENT)?
+        };
+        let command_pool = CommandPool::new(device, command_pool);
+        let staging = Buffer::new(
+            device,
+            allocator,
+            STAGING_SIZE,
+            buffer::Usage::TRANSFER_SRC,
+            MemoryUsage::HostVisible,
+        )?;
+        Ok(Self {
+            command_pool,
+            staging,
+            head: 0,
+            tail: 0,
+            recording: None,
+            in_flight: VecDeque::new(),
+        })
+    }
+
+    /// Copies `data` into `dst` at `dst_offset`. The copy happens on the GPU before the
+    /// next frame, and `dst` must stay alive until it has.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn upload_buffer<T: Copy>(
+        &mut self,
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        queue: &mut CommandQueue<back::Backend, Graphics>,
+        data: &[T],
+        dst: &<back::Backend as Backend>::Buffer,
+        dst_offset: u64,
+    ) -> Result<(), RendererError> {
+        let size = std::mem::size_of_val(data) as u64;
+        let (dedicated, src_offset) = self.stage(device, allocator, queue, data)?;
+        let batch = self.recording.as_mut().unwrap();
+        let src = if dedicated {
+            batch.dedicated.last().unwrap()
+        } else {
+            &self.staging
+        };
+        batch.command_buffer.copy_buffer(
+            src,
+            dst,
+            Some(BufferCopy {
+                src: src_offset,
+                dst: dst_offset,
+                size,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Copies tightly packed texels into `layers` of mip `level` of `image`, which is
+    /// left in `ShaderReadOnlyOptimal`. Anything already in that part of the image
+    /// is discarded.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn upload_image(
+        &mut self,
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        queue: &mut CommandQueue<back::Backend, Graphics>,
+        data: &[u8],
+        image: &<back::Backend as Backend>::Image,
+        extent: Extent,
+        level: u8,
+        layers: Range<u16>,
+    ) -> Result<(), RendererError> {
+        let (dedicated, src_offset) = self.stage(device, allocator, queue, data)?;
+        let batch = self.recording.as_mut().unwrap();
+        let src = if dedicated {
+            batch.dedicated.last().unwrap()
+        } else {
+            &self.staging
+        };
+        let range = SubresourceRange {
+            aspects: Aspects::COLOR,
+            levels: level..level + 1,
+            layers: layers.clone(),
+        };
+        batch.command_buffer.pipeline_barrier(
+            PipelineStage::TOP_OF_PIPE..PipelineStage::TRANSFER,
+            Dependencies::empty(),
+            &[Barrier::Image {
+                states: (Access::empty(), Layout::Undefined)
+                    ..(Access::TRANSFER_WRITE, Layout::TransferDstOptimal),
+                target: image,
+                families: None,
+                range: range.clone(),
+            }],
+        );
+        batch.command_buffer.copy_buffer_to_image(
+            src,
+            image,
+            Layout::TransferDstOptimal,
+            &[BufferImageCopy {
+                buffer_offset: src_offset,
+                buffer_width: extent.width,
+                buffer_height: extent.height,
+                image_layers: SubresourceLayers {
+                    aspects: Aspects::COLOR,
+                    level,
+                    layers,
+                },
+                image_offset: Offset { x: 0, y: 0, z: 0 },
+                image_extent: extent,
+            }],
+        );
+        batch.command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+            Dependencies::empty(),
+            &[Barrier::Image {
+                states: (Access::TRANSFER_WRITE, Layout::TransferDstOptimal)
+                    ..(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+                target: image,
+                families: None,
+                range,
+            }],
+        );
+        Ok(())
+    }
+
+    /// Copies `data` into staging memory and makes sure a batch is recording. Returns
+    /// whether the data went into a dedicated staging buffer, the batch's last, instead
+    /// of the ring, and the offset to copy from.
+    unsafe fn stage<T: Copy>(
+        &mut self,
+        device: &DeviceHandle,
+        allocator: &mut Allocator,
+        queue: &mut CommandQueue<back::Backend, Graphics>,
+        data: &[T],
+    ) -> Result<(bool, u64), RendererError> {
+        let size = std::mem::size_of_val(data) as u64;
+        if size == 0 {
+            return Err(RendererError::InvalidArgument("Uploads can't be empty!"));
+        }
+
+        if size > STAGING_SIZE / 4 {
+            let buffer = Buffer::new(
+                device,
+                allocator,
+                size,
+                buffer::Usage::TRANSFER_SRC,
+                MemoryUsage::Transient,
+            )?;
+            allocator.write(buffer.allocation(), 0, data)?;
+            self.begin_batch().dedicated.push(buffer);
+            return Ok((true, 0));
+        }
+
+        let offset = loop {
+            if let Some(offset) = self.ring_alloc(size) {
+                break offset;
+            }
+            // The ring is full of uploads the GPU hasn't done yet, so hand over what
+            // we have and wait for the oldest batch.
+            if self.in_flight.is_empty() {
+                self.flush(device, queue)?;
+            }
+            self.wait_oldest(device)?;
+        };
+        allocator.write(self.staging.allocation(), offset, data)?;
+        self.begin_batch();
+        Ok((false, offset))
+    }
+
+    /// The batch being recorded, starting a new one if needed.
+    unsafe fn begin_batch(&mut self) -> &mut Batch {
+        if self.recording.is_none() {
+            let mut command_buffer = self.command_pool.acquire_command_buffer::<OneShot>();
+            command_buffer.begin();
+            self.recording = Some(Batch {
+                command_buffer,
+                ring_end: 0,
+                dedicated: Vec::new(),
+                fence: None,
+            });
+        }
+        self.recording.as_mut().unwrap()
+    }
+
+    /// Finds `size` bytes of the ring that no batch is using.
+    fn ring_alloc(&mut self, size: u64) -> Option<u64> {
+        // The head only catches up with the tail once nothing in the ring is in use.
+        if self.head == self.tail {
+            self.head = 0;
+            self.tail = 0;
+        }
+        let start = align_up(self.head, STAGING_ALIGNMENT);
+        let offset = if self.head >= self.tail {
+            // Free space is from the head to the end, then from the start to the tail.
+            // Stopping short of the tail keeps a full ring from looking empty.
+            if start + size <= STAGING_SIZE {
+                start
+            } else if size < self.tail {
+                0
+            } else {
+                return None;
+            }
+        } else if start + size < self.tail {
+            start
+        } else {
+            return None;
+        };
+        self.head = offset + size;
+        Some(offset)
+    }
+
+    /// Submits everything recorded since the last flush.
+    pub fn flush(
+        &mut self,
+        device: &DeviceHandle,
+        queue: &mut CommandQueue<back::Backend, Graphics>,
+    ) -> Result<(), RendererError> {
+        let mut batch = match self.recording.take() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        unsafe {
+            batch.command_buffer.pipeline_barrier(
+                PipelineStage::TRANSFER
+                    ..PipelineStage::VERTEX_INPUT
+                        | PipelineStage::VERTEX_SHADER
+                        | PipelineStage::FRAGMENT_SHADER,
+                Dependencies::empty(),
+                &[Barrier::AllBuffers(
+                    buffer::Access::TRANSFER_WRITE
+                        ..buffer::Access::VERTEX_BUFFER_READ
+                            | buffer::Access::INDEX_BUFFER_READ
+                            | buffer::Access::CONSTANT_BUFFER_READ
+                            | buffer::Access::SHADER_READ,
+                )],
+            );
+            batch.command_buffer.finish();
+            let fence = Fence::new(device, device.create_fence(false)?);
+            queue.submit_nosemaphores(Some(&batch.command_buffer), Some(&*fence));
+            batch.fence = Some(fence);
+        }
+        batch.ring_end = self.head;
+        self.in_flight.push_back(batch);
+        Ok(())
+    }
+
+    /// Reclaims the staging memory of every batch the GPU has finished.
+    pub fn poll(&mut self, device: &back::Device) -> Result<(), RendererError> {
+        while let Some(batch) = self.in_flight.front() {
+            let done = unsafe { device.get_fence_status(batch.fence.as_ref().unwrap())? };
+            if !done {
+                break;
+            }
+            self.retire_oldest();
+        }
+        Ok(())
+    }
+
+    /// Submits anything still recording and blocks until every upload is done.
+    pub fn finish(
+        &mut self,
+        device: &DeviceHandle,
+        queue: &mut CommandQueue<back::Backend, Graphics>,
+    ) -> Result<(), RendererError> {
+        self.flush(device, queue)?;
+        while !self.in_flight.is_empty() {
+            self.wait_oldest(device)?;
+        }
+        Ok(())
+    }
+
+    fn wait_oldest(&mut self, device: &back::Device) -> Result<(), RendererError> {
+        if let Some(batch) = self.in_flight.front() {
+            unsafe { device.wait_for_fence(batch.fence.as_ref().unwrap(), u64::MAX)? };
+            self.retire_oldest();
+        }
+        Ok(())
+    }
+
+    /// Frees the oldest batch's part of the ring. Its fence and dedicated staging
+    /// buffers are retired with it, so they go once the next frame is done.
+    fn retire_oldest(&mut self) {
+        let batch = self.in_flight.pop_front().unwrap();
+        self.tail = batch.ring_end;
+        unsafe { self.command_pool.free(Some(batch.command_buffer)) };
+    }
+}